@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+
+use crate::{
+    blockers::BlockersForm,
+    channel::ChannelForm,
+    config::Config,
+    cpu_loadgen::CpuLoadGenForm,
+    generator::{self, LoadGenerator, ValidationError},
+    jobs,
+    rediskeys::RedisKeysForm,
+    sleeper::SleeperForm,
+    AppState,
+};
+
+/// JSON counterpart of the form routes, nested at `/api/v1`.
+pub fn router(config: &Config) -> Router<Arc<AppState>> {
+    let mut api = Router::new()
+        .route("/sleeper", post(start::<SleeperForm>))
+        .route("/channel", post(start::<ChannelForm>))
+        .route("/jobs", get(jobs::list))
+        .route("/jobs/:id", get(jobs::get))
+        .route("/jobs/:id", delete(jobs::cancel));
+    if config.features.cpu_load {
+        api = api.route("/cpuloadgen", post(start::<CpuLoadGenForm>));
+    }
+    if config.features.blockers {
+        api = api.route("/blockers", post(start::<BlockersForm>));
+    }
+    if config.features.redis {
+        api = api.route("/rediskeys", post(start::<RedisKeysForm>));
+    }
+    api
+}
+
+async fn start<G: LoadGenerator>(
+    State(app): State<Arc<AppState>>,
+    body: Result<Json<G>, JsonRejection>,
+) -> Response {
+    let Json(g) = match body {
+        Ok(body) => body,
+        Err(rejection) => return rejected(rejection),
+    };
+    match generator::start(&app, g).await {
+        Ok(started) => (StatusCode::ACCEPTED, Json(started)).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Same error body as a failed validation, 400 for broken JSON and 422 for
+/// missing or mistyped parameters.
+fn rejected(rejection: JsonRejection) -> Response {
+    ValidationError::new("body", rejection.body_text()).with_status(rejection.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        extract::{FromRequest, Request},
+        http::header,
+    };
+    use serde_json::Value;
+
+    use super::*;
+
+    async fn post(content_type: &str, body: &'static str) -> (StatusCode, Value) {
+        let request = Request::post("/api/v1/sleeper")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap();
+        let rejection = Json::<SleeperForm>::from_request(request, &())
+            .await
+            .err()
+            .expect("body was accepted");
+        let response = rejected(rejection);
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn rejected_bodies_are_json_errors() {
+        let (status, body) = post("application/json", "{\"tasks\": ").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["field"], "body");
+        let (status, body) = post("application/json", r#"{"tasks": "many", "time": 1}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["error"]["message"].as_str().unwrap().contains("tasks"));
+        let (status, body) = post("text/plain", r#"{"tasks": 1, "time": 1}"#).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["error"]["field"], "body");
+    }
+}
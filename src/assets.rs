@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+
+use axum::Router;
+
+use crate::config::Config;
+
+#[cfg(feature = "embed")]
+pub use embedded::Templates as EmbeddedTemplates;
+
+/// Serves `/static`, either from the binary or from `<assets_dir>/static`.
+/// Missing files are answered with a 404 either way.
+pub fn routes<S>(router: Router<S>, config: &Config) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    #[cfg(feature = "embed")]
+    if config.assets_dir.is_none() {
+        log::info!("serving embedded static files");
+        return router.route("/static/*path", axum::routing::get(embedded::static_file));
+    }
+    let dir = static_dir(config);
+    log::info!("serving static files from {}", dir.display());
+    router.nest_service("/static", tower_http::services::ServeDir::new(dir))
+}
+
+/// On-disk directory of the static files.
+fn static_dir(config: &Config) -> PathBuf {
+    match &config.assets_dir {
+        Some(dir) => dir.join("static"),
+        None => "static".into(),
+    }
+}
+
+#[cfg(feature = "embed")]
+mod embedded {
+    use std::fmt::Write;
+
+    use axum::{
+        extract::Path,
+        http::{header, HeaderMap, StatusCode},
+        response::{IntoResponse, Response},
+    };
+    use rust_embed::RustEmbed;
+
+    #[derive(RustEmbed)]
+    #[folder = "static/"]
+    struct Static;
+
+    #[derive(RustEmbed)]
+    #[folder = "templates/"]
+    pub struct Templates;
+
+    /// Embedded files only change with the binary, so clients revalidate with
+    /// the content hash instead of downloading them again.
+    pub async fn static_file(Path(path): Path<String>, headers: HeaderMap) -> Response {
+        let Some(file) = Static::get(&path) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let etag = file
+            .metadata
+            .sha256_hash()
+            .iter()
+            .fold(String::from("\""), |mut etag, byte| {
+                let _ = write!(etag, "{byte:02x}");
+                etag
+            })
+            + "\"";
+        let cache_headers = [
+            (header::ETAG, etag.clone()),
+            (header::CACHE_CONTROL, "no-cache".to_string()),
+        ];
+        let not_modified = headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.split(',').any(|tag| tag.trim() == etag));
+        if not_modified {
+            return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+        }
+        (
+            [(header::CONTENT_TYPE, file.metadata.mimetype().to_string())],
+            cache_headers,
+            file.data,
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    /// Status line of a GET request to `router`.
+    async fn status(router: Router, path: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn missing_static_files_are_not_found() {
+        let config = Config {
+            assets_dir: Some(".".into()),
+            ..Config::default()
+        };
+        let router = || routes(Router::new(), &config);
+        assert_eq!(
+            status(router(), "/static/field.js").await,
+            "HTTP/1.1 200 OK"
+        );
+        assert_eq!(
+            status(router(), "/static/missing.js").await,
+            "HTTP/1.1 404 Not Found"
+        );
+        // a wrong working directory only makes the files go missing
+        let config = Config {
+            assets_dir: Some("no-such-dir".into()),
+            ..Config::default()
+        };
+        let router = routes(Router::new(), &config);
+        assert_eq!(
+            status(router, "/static/field.js").await,
+            "HTTP/1.1 404 Not Found"
+        );
+    }
+
+    #[cfg(feature = "embed")]
+    #[tokio::test]
+    async fn embedded_files_are_served() {
+        let router = || routes(Router::new(), &Config::default());
+        assert_eq!(
+            status(router(), "/static/field.js").await,
+            "HTTP/1.1 200 OK"
+        );
+        assert_eq!(
+            status(router(), "/static/missing.js").await,
+            "HTTP/1.1 404 Not Found"
+        );
+    }
+}
@@ -1,49 +1,87 @@
-use std::{thread::sleep, time::Duration};
+use std::{
+    sync::Arc,
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
-use axum::Form;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
 
-#[derive(Deserialize)]
+use crate::{
+    config::Config,
+    generator::{self, LoadGenerator, ValidationError},
+    jobs,
+    limits::Limits,
+};
+
+#[derive(Deserialize, Serialize)]
 pub struct BlockersForm {
     tasks: u64,
     time: u64,
-    spawn_blocking: Option<String>,
+    #[serde(default, deserialize_with = "generator::checkbox")]
+    spawn_blocking: bool,
 }
 
-impl BlockersForm {
-    fn spawn_blocking(&self) -> bool {
-        let Some(blocking) = &self.spawn_blocking else {
-            return false;
-        };
-        matches!(blocking.to_lowercase().as_str(), "on" | "true" | "1")
+impl LoadGenerator for BlockersForm {
+    const KIND: &'static str = "blockers";
+
+    fn validate(&self, limits: &Limits) -> Result<(), ValidationError> {
+        limits.blockers.tasks.check("tasks", self.tasks)?;
+        limits.blockers.time.check("time", self.time)
     }
-}
 
-pub async fn blockers(Form(f): Form<BlockersForm>) {
-    log::info!("test");
-    log::info!(
-        "[blockers] spawning {} tasks for {} seconds in {} mode",
-        f.tasks,
-        f.time,
-        if f.spawn_blocking() {
-            "blocking"
+    fn estimated_duration(&self, config: &Config) -> Option<Duration> {
+        // tasks run in waves of as many threads as the runtime offers
+        let threads = if self.spawn_blocking {
+            config.sync_worker_threads
         } else {
-            "nonblocking"
+            tokio::runtime::Handle::current().metrics().num_workers()
+        } as u64;
+        let waves = self.tasks.div_ceil(threads.max(1));
+        Some(Duration::from_secs(self.time.saturating_mul(waves)))
+    }
+
+    async fn run(self, _config: Arc<Config>, token: CancellationToken) -> Result<(), String> {
+        log::info!(
+            "[blockers] spawning {} tasks for {} seconds in {} mode",
+            self.tasks,
+            self.time,
+            if self.spawn_blocking {
+                "blocking"
+            } else {
+                "nonblocking"
+            }
+        );
+        let time = Duration::from_secs(self.time);
+        let mut handles = Vec::with_capacity(self.tasks as usize);
+        for i in 0..self.tasks {
+            let token = token.clone();
+            if self.spawn_blocking {
+                handles.push(tokio::runtime::Handle::current().spawn_blocking(move || {
+                    log::info!("[blockers] task {i} spawned (blocking)");
+                    blocking_wait(time, &token);
+                    log::info!("[blockers] task {i} ending");
+                }));
+            } else {
+                handles.push(tokio::spawn(async move {
+                    log::info!("[blockers] task {i} spawned (blocking)");
+                    blocking_wait(time, &token);
+                    log::info!("[blockers] task {i} ending");
+                }));
+            }
         }
-    );
-    for i in 0..f.tasks {
-        if f.spawn_blocking() {
-            tokio::runtime::Handle::current().spawn_blocking(move || {
-                log::info!("[blockers] task {i} spawned (blocking)");
-                sleep(Duration::from_secs(f.time));
-                log::info!("[blockers] task {i} ending");
-            });
-        } else {
-            tokio::spawn(async move {
-                log::info!("[blockers] task {i} spawned (blocking)");
-                sleep(Duration::from_secs(f.time));
-                log::info!("[blockers] task {i} ending");
-            });
+        jobs::join_all(handles).await
+    }
+}
+
+// blocks the current thread, but checks the cancellation token every now and then
+fn blocking_wait(time: Duration, token: &CancellationToken) {
+    let end = Instant::now() + time;
+    while !token.is_cancelled() {
+        let now = Instant::now();
+        if now >= end {
+            return;
         }
+        sleep((end - now).min(Duration::from_millis(100)));
     }
 }
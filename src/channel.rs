@@ -1,18 +1,39 @@
-use axum::Form;
-use serde::Deserialize;
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
 
-#[derive(Deserialize)]
+use crate::{
+    config::Config,
+    generator::{LoadGenerator, ValidationError},
+    jobs,
+    limits::Limits,
+};
+
+#[derive(Deserialize, Serialize)]
 pub struct ChannelForm {
     tasks: u64,
     time: u64,
     repeat: u64,
 }
 
-pub async fn channel(Form(f): Form<ChannelForm>) {
-    let time = Duration::from_secs(f.time);
-    let c = Channel::new(f.tasks, time, f.repeat);
-    c.spawn().await;
+impl LoadGenerator for ChannelForm {
+    const KIND: &'static str = "channel";
+
+    fn validate(&self, limits: &Limits) -> Result<(), ValidationError> {
+        limits.channel.tasks.check("tasks", self.tasks)?;
+        limits.channel.time.check("time", self.time)?;
+        limits.channel.repeat.check("repeat", self.repeat)
+    }
+
+    fn estimated_duration(&self, _config: &Config) -> Option<Duration> {
+        Some(Duration::from_secs(self.time.saturating_mul(self.repeat)))
+    }
+
+    async fn run(self, _config: Arc<Config>, token: CancellationToken) -> Result<(), String> {
+        Channel::new(self.tasks, Duration::from_secs(self.time), self.repeat)
+            .run(token)
+            .await
+    }
 }
 
 #[derive(PartialEq)]
@@ -35,27 +56,33 @@ impl Channel {
             repeat,
         }
     }
-    pub async fn spawn(&self) {
+    pub async fn run(self, token: CancellationToken) -> Result<(), String> {
         let (tx, rx) = tokio::sync::watch::channel(Message::Ping);
         let mut count = self.repeat;
         let wait = self.time;
+        let mut handles = Vec::with_capacity(self.tasks as usize + 1);
         // spawn sending task
-        tokio::task::spawn(async move {
+        handles.push(tokio::task::spawn(async move {
             while count > 0 {
                 tx.send(Message::Ping).expect("error sending ping command");
                 log::debug!("sent ping #{count}");
-                tokio::time::sleep(wait).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = token.cancelled() => break,
+                }
                 count -= 1;
             }
             tx.send(Message::Terminate)
                 .expect("error sending Terminate command");
-        });
+        }));
         // spawn multiple receiving tasks
         for _ in 0..self.tasks {
             let mut rx = rx.clone();
-            tokio::task::spawn(async move {
+            handles.push(tokio::task::spawn(async move {
                 let _ = rx.wait_for(|x| x == &Message::Terminate).await;
-            });
+            }));
         }
+        drop(rx);
+        jobs::join_all(handles).await
     }
 }
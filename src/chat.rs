@@ -15,8 +15,9 @@ use std::{
     sync::Arc,
 };
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 
-use crate::AppState;
+use crate::{shutdown, AppState};
 
 #[derive(Deserialize)]
 pub struct Chatform {
@@ -57,10 +58,11 @@ pub async fn websocket_handler(
     ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
     let chat = Arc::clone(&app.chat);
-    ws.on_upgrade(move |socket| handle_socket(socket, chat, id))
+    let shutdown = app.shutdown.clone();
+    ws.on_upgrade(move |socket| handle_socket(socket, chat, id, shutdown))
 }
 
-async fn handle_socket(ws: WebSocket, chat: Arc<Chat>, id: u64) {
+async fn handle_socket(ws: WebSocket, chat: Arc<Chat>, id: u64, shutdown: CancellationToken) {
     let Some(user) = chat.find(id).await else {
         log::warn!("user with id '{id}' cannot be found");
         let _ = ws.close().await;
@@ -121,7 +123,17 @@ async fn handle_socket(ws: WebSocket, chat: Arc<Chat>, id: u64) {
 
     let chat_user = user.clone();
     let mut chat_to_ws_task = tokio::spawn(async move {
-        while let Ok(msg) = chat_rx.recv().await {
+        loop {
+            let msg = tokio::select! {
+                msg = chat_rx.recv() => msg,
+                _ = shutdown.cancelled() => {
+                    let _ = ws_send.send(shutdown::close_message()).await;
+                    break;
+                }
+            };
+            let Ok(msg) = msg else {
+                break;
+            };
             log::debug!("user '{}' receives: {}", chat_user, msg.log());
             if ws_send
                 .send(Message::Text(msg.websocket_reply()))
@@ -1,51 +1,67 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use axum::Form;
-use serde::Deserialize;
-use tokio::{
-    sync::broadcast::{error::TryRecvError, Receiver},
-    task::JoinHandle,
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::Config,
+    generator::{LoadGenerator, ValidationError},
+    jobs,
+    limits::Limits,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct CpuLoadGenForm {
     threads: u64,
     duration: u64,
 }
 
-pub async fn load_gen_threads(Form(f): Form<CpuLoadGenForm>) {
-    tokio::task::spawn(async move {
-        log::info!("spawning {} load threads", f.threads);
+impl LoadGenerator for CpuLoadGenForm {
+    const KIND: &'static str = "cpuloadgen";
+
+    fn validate(&self, limits: &Limits) -> Result<(), ValidationError> {
+        limits.cpuloadgen.threads.check("threads", self.threads)?;
+        limits.cpuloadgen.duration.check("duration", self.duration)
+    }
+
+    fn estimated_duration(&self, _config: &Config) -> Option<Duration> {
+        Some(Duration::from_secs(self.duration))
+    }
+
+    async fn run(self, _config: Arc<Config>, token: CancellationToken) -> Result<(), String> {
+        log::info!("spawning {} load threads", self.threads);
         let mut jobs: Vec<JoinHandle<()>> = vec![];
-        let (tx, _) = tokio::sync::broadcast::channel(1);
-        for _ in 0..(f.threads) {
-            let mut rx: Receiver<bool> = tx.subscribe();
+        // the threads only look at this child token, so they can be stopped
+        // without marking the whole job as cancelled
+        let stop = token.child_token();
+        for _ in 0..(self.threads) {
+            let stop = stop.clone();
             jobs.push(tokio::runtime::Handle::current().spawn_blocking(move || {
                 let mut f_n: u128 = 0;
                 loop {
                     let n = f_n + 1337;
                     f_n *= n;
-                    // return if not empty or closed
-                    match rx.try_recv() {
-                        Err(TryRecvError::Empty) => {}
-                        _ => return,
+                    // return if the job ended or got cancelled
+                    if stop.is_cancelled() {
+                        return;
                     }
                 }
             }));
         }
-        tokio::time::sleep(Duration::from_secs(f.duration)).await;
-        log::info!(
-            "aborting {} threads after {} seconds",
-            f.threads,
-            f.duration
-        );
-        if let Err(e) = tx.send(true) {
-            log::error!("{}", e);
-        };
-        for job in jobs {
-            if let Err(e) = job.await {
-                log::error!("{}", e);
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(self.duration)) => {
+                log::info!(
+                    "aborting {} threads after {} seconds",
+                    self.threads,
+                    self.duration
+                );
+            }
+            _ = token.cancelled() => {
+                log::info!("aborting {} threads on request", self.threads);
             }
         }
-    });
+        stop.cancel();
+        jobs::join_all(jobs).await
+    }
 }
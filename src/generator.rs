@@ -0,0 +1,201 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{rejection::FormRejection, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    Form, Json,
+};
+use minijinja::HtmlEscape;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::{config::Config, jobs::JobInfo, limits::Limits, AppState};
+
+/// Common interface of all load generators, used by the HTMX form routes and
+/// the JSON API alike.
+pub trait LoadGenerator: DeserializeOwned + Serialize + Send + 'static {
+    const KIND: &'static str;
+
+    fn validate(&self, limits: &Limits) -> Result<(), ValidationError>;
+
+    /// Rough wall clock estimate, `None` if it depends on external systems.
+    fn estimated_duration(&self, config: &Config) -> Option<Duration>;
+
+    fn run(
+        self,
+        config: Arc<Config>,
+        token: CancellationToken,
+    ) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidationError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field,
+            message: message.into(),
+        }
+    }
+
+    fn html(&self) -> Html<String> {
+        // rejection messages can echo the submitted values
+        Html(format!(
+            r#"<div class="alert alert-danger" role="alert"><b>{}</b>: {}</div>"#,
+            HtmlEscape(self.field),
+            HtmlEscape(&self.message)
+        ))
+    }
+}
+
+impl ValidationError {
+    /// The JSON error body with another status than 422, e.g. 400 for a body
+    /// which is not JSON at all.
+    pub fn with_status(self, status: StatusCode) -> Response {
+        (status, Json(json!({ "error": self }))).into_response()
+    }
+}
+
+impl IntoResponse for ValidationError {
+    fn into_response(self) -> Response {
+        self.with_status(StatusCode::UNPROCESSABLE_ENTITY)
+    }
+}
+
+#[derive(Serialize)]
+pub struct Started {
+    pub job: JobInfo,
+    pub params: Value,
+    pub estimated_duration_secs: Option<u64>,
+}
+
+/// Validates the parameters and registers the generator run as a job.
+pub async fn start<G: LoadGenerator>(
+    app: &Arc<AppState>,
+    generator: G,
+) -> Result<Started, ValidationError> {
+    generator.validate(&app.config.limits)?;
+    let params = serde_json::to_value(&generator).unwrap_or_default();
+    let estimated_duration_secs = generator
+        .estimated_duration(&app.config)
+        .map(|d| d.as_secs());
+    let config = Arc::clone(&app.config);
+    let job = app
+        .jobs
+        .start(G::KIND, params.clone(), |_, token| {
+            generator.run(config, token)
+        })
+        .await;
+    Ok(Started {
+        job,
+        params,
+        estimated_duration_secs,
+    })
+}
+
+/// Form route of a generator. Replies with an HTML snippet which is swapped into
+/// the form, including validation errors (422).
+pub async fn form<G: LoadGenerator>(
+    State(app): State<Arc<AppState>>,
+    generator: Result<Form<G>, FormRejection>,
+) -> Response {
+    let Form(generator) = match generator {
+        Ok(generator) => generator,
+        Err(rejection) => {
+            let e = ValidationError::new("form", rejection.body_text());
+            return (StatusCode::UNPROCESSABLE_ENTITY, e.html()).into_response();
+        }
+    };
+    match start(&app, generator).await {
+        Ok(started) => {
+            let estimate = started
+                .estimated_duration_secs
+                .map(|secs| format!(", estimated duration {secs}s"))
+                .unwrap_or_default();
+            Html(format!(
+                r#"<div class="alert alert-success" role="alert">started job #{}{estimate}</div>"#,
+                started.job.id
+            ))
+            .into_response()
+        }
+        Err(e) => (StatusCode::UNPROCESSABLE_ENTITY, e.html()).into_response(),
+    }
+}
+
+/// Accepts HTML checkbox values ("on") as well as JSON booleans.
+pub fn checkbox<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Checkbox {
+        Bool(bool),
+        Text(String),
+    }
+    Ok(match Checkbox::deserialize(deserializer)? {
+        Checkbox::Bool(b) => b,
+        Checkbox::Text(s) => matches!(s.to_lowercase().as_str(), "on" | "true" | "1"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        extract::{FromRequest, Request},
+        http::header,
+    };
+
+    use super::*;
+    use crate::blockers::BlockersForm;
+
+    async fn form<G: LoadGenerator>(body: &'static str) -> Result<G, FormRejection> {
+        let request = Request::post("/")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .unwrap();
+        Form::<G>::from_request(request, &()).await.map(|f| f.0)
+    }
+
+    #[tokio::test]
+    async fn forms_and_json_read_the_same_parameters() {
+        let from_form: BlockersForm = form("tasks=2&time=3&spawn_blocking=on").await.unwrap();
+        let from_json: BlockersForm =
+            serde_json::from_str(r#"{"tasks": 2, "time": 3, "spawn_blocking": true}"#).unwrap();
+        assert_eq!(
+            serde_json::to_value(from_form).unwrap(),
+            serde_json::to_value(from_json).unwrap()
+        );
+        // unchecked boxes are not submitted at all
+        let unchecked: BlockersForm = form("tasks=2&time=3").await.unwrap();
+        assert_eq!(
+            serde_json::to_value(unchecked).unwrap()["spawn_blocking"],
+            false
+        );
+        assert!(form::<BlockersForm>("tasks=two&time=3").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn validation_errors_are_unprocessable() {
+        let response = ValidationError::new("tasks", "too many").into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({"error": {"field": "tasks", "message": "too many"}})
+        );
+    }
+
+    #[test]
+    fn html_errors_are_escaped() {
+        let e = ValidationError::new("form", "unknown variant `<script>alert(1)</script>`");
+        let Html(html) = e.html();
+        assert!(!html.contains("<script>"), "{html}");
+        assert!(html.contains("&lt;script&gt;"), "{html}");
+    }
+}
@@ -0,0 +1,427 @@
+use std::{collections::VecDeque, path::PathBuf, sync::Arc};
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::Mutex,
+};
+
+use crate::{config::Config, generator::ValidationError, AppState};
+
+/// Most samples a single history request returns. Without `from` these are the
+/// newest ones, so the downloads cover the current run, with `from` the oldest
+/// ones after it, to page through longer histories.
+pub const HISTORY_ROWS_MAX: usize = 10_000;
+
+/// On-disk copy of every stats sample, configured with `stats_sink`:
+/// `jsonl:<path>` appends one JSON object per line, `sqlite:<path>` stores
+/// the samples in a SQLite database.
+pub enum HistorySink {
+    Jsonl {
+        path: PathBuf,
+        file: Arc<Mutex<tokio::fs::File>>,
+    },
+    Sqlite(Arc<std::sync::Mutex<rusqlite::Connection>>),
+}
+
+impl HistorySink {
+    pub async fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        let Some(sink) = &config.stats_sink else {
+            return Ok(None);
+        };
+        let Some((kind, path)) = sink.split_once(':') else {
+            return Err(format!(
+                "stats_sink '{sink}' must be 'jsonl:<path>' or 'sqlite:<path>'"
+            ));
+        };
+        match kind {
+            "jsonl" => Self::jsonl(path.into()).await.map(Some),
+            "sqlite" => Self::sqlite(path.into()).await.map(Some),
+            _ => Err(format!("unknown stats_sink type '{kind}'")),
+        }
+    }
+
+    async fn jsonl(path: PathBuf) -> Result<Self, String> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .map_err(|e| format!("cannot open {}: {e}", path.display()))?;
+        log::info!("writing stats history to {}", path.display());
+        Ok(Self::Jsonl {
+            path,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    async fn sqlite(path: PathBuf) -> Result<Self, String> {
+        let display = path.display().to_string();
+        let conn = tokio::task::spawn_blocking(move || {
+            let conn = rusqlite::Connection::open(path)?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS stats (ts INTEGER NOT NULL, sample TEXT NOT NULL)",
+                (),
+            )?;
+            conn.execute("CREATE INDEX IF NOT EXISTS stats_ts ON stats (ts)", ())?;
+            Ok::<_, rusqlite::Error>(conn)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("cannot open {display}: {e}"))?;
+        log::info!("writing stats history to {display}");
+        Ok(Self::Sqlite(Arc::new(std::sync::Mutex::new(conn))))
+    }
+
+    pub async fn write(&self, sample: &Value) {
+        let result = match self {
+            HistorySink::Jsonl { file, .. } => {
+                let mut line = sample.to_string();
+                line.push('\n');
+                let mut file = file.lock().await;
+                // tokio keeps the last write in flight until it is flushed, a
+                // read right after would miss the sample
+                match file.write_all(line.as_bytes()).await {
+                    Ok(()) => file.flush().await.map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            HistorySink::Sqlite(conn) => {
+                let conn = Arc::clone(conn);
+                let ts = sample_time(sample).map(|t| t.timestamp_millis());
+                let text = sample.to_string();
+                tokio::task::spawn_blocking(move || {
+                    conn.lock()
+                        .map_err(|e| e.to_string())?
+                        .execute(
+                            "INSERT INTO stats (ts, sample) VALUES (?1, ?2)",
+                            (ts.unwrap_or_default(), text),
+                        )
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|r| r)
+            }
+        };
+        if let Err(e) = result {
+            log::error!("cannot write stats history: {e}");
+        }
+    }
+
+    /// At most `limit` samples between `from` and `to`, oldest first. Without
+    /// `from` the newest samples are kept, see `HISTORY_ROWS_MAX`.
+    pub async fn read(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: usize,
+    ) -> Result<Vec<Value>, String> {
+        match self {
+            HistorySink::Jsonl { path, .. } => {
+                let file = tokio::fs::File::open(path)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let mut lines = BufReader::new(file).lines();
+                let mut samples = VecDeque::new();
+                while from.is_none() || samples.len() < limit {
+                    let Some(line) = lines.next_line().await.map_err(|e| e.to_string())? else {
+                        break;
+                    };
+                    let Ok(sample) = serde_json::from_str::<Value>(&line) else {
+                        continue;
+                    };
+                    // samples are appended in order, nothing after this one is
+                    // in range either
+                    if to.is_some_and(|to| sample_time(&sample).is_some_and(|time| time > to)) {
+                        break;
+                    }
+                    if in_range(&sample, from, to) {
+                        samples.push_back(sample);
+                    }
+                    // the file is read to the end to find the newest ones,
+                    // memory only holds `limit` of them
+                    if samples.len() > limit {
+                        samples.pop_front();
+                    }
+                }
+                Ok(samples.into())
+            }
+            HistorySink::Sqlite(conn) => {
+                let conn = Arc::clone(conn);
+                let query = match from {
+                    Some(_) => {
+                        "SELECT sample FROM stats WHERE ts >= ?1 AND ts <= ?2 ORDER BY ts LIMIT ?3"
+                    }
+                    None => {
+                        "SELECT sample FROM (SELECT ts, sample FROM stats \
+                         WHERE ts >= ?1 AND ts <= ?2 ORDER BY ts DESC LIMIT ?3) ORDER BY ts"
+                    }
+                };
+                let from = from.map(|t| t.timestamp_millis()).unwrap_or(i64::MIN);
+                let to = to.map(|t| t.timestamp_millis()).unwrap_or(i64::MAX);
+                let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+                tokio::task::spawn_blocking(move || {
+                    let conn = conn.lock().map_err(|e| e.to_string())?;
+                    let mut stmt = conn.prepare(query).map_err(|e| e.to_string())?;
+                    let rows = stmt
+                        .query_map((from, to, limit), |row| row.get::<_, String>(0))
+                        .map_err(|e| e.to_string())?;
+                    Ok(rows
+                        .filter_map(|row| row.ok())
+                        .filter_map(|sample| serde_json::from_str(&sample).ok())
+                        .collect())
+                })
+                .await
+                .map_err(|e| e.to_string())?
+            }
+        }
+    }
+}
+
+fn sample_time(sample: &Value) -> Option<DateTime<Utc>> {
+    sample["time"]
+        .as_str()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.with_timezone(&Utc))
+}
+
+pub fn in_range(sample: &Value, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> bool {
+    let Some(time) = sample_time(sample) else {
+        return false;
+    };
+    from.is_none_or(|from| time >= from) && to.is_none_or(|to| time <= to)
+}
+
+/// Flattens the samples into CSV, one column per top level field. Nested
+/// values end up as JSON strings.
+fn to_csv(samples: &[Value]) -> String {
+    let mut columns: Vec<String> = vec![];
+    for sample in samples {
+        if let Some(object) = sample.as_object() {
+            for key in object.keys() {
+                if !columns.contains(key) {
+                    columns.push(key.clone());
+                }
+            }
+        }
+    }
+    let mut csv = columns.join(",");
+    csv.push('\n');
+    for sample in samples {
+        let row: Vec<String> = columns
+            .iter()
+            .map(|column| match &sample[column] {
+                Value::Null => String::new(),
+                Value::String(s) => csv_field(s),
+                value @ (Value::Array(_) | Value::Object(_)) => csv_field(&value.to_string()),
+                value => value.to_string(),
+            })
+            .collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+impl HistoryQuery {
+    fn validate(&self) -> Result<(), ValidationError> {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) if from > to => Err(ValidationError::new(
+                "from",
+                format!("{from} is after to {to}"),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+async fn samples(app: &AppState, query: HistoryQuery) -> Result<Vec<Value>, Response> {
+    query.validate().map_err(IntoResponse::into_response)?;
+    app.stats
+        .history(query.from, query.to, HISTORY_ROWS_MAX)
+        .await
+        .map_err(|e| {
+            log::error!("cannot read stats history: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, e).into_response()
+        })
+}
+
+pub async fn history(
+    State(app): State<Arc<AppState>>,
+    Query(query): Query<HistoryQuery>,
+) -> impl IntoResponse {
+    match samples(&app, query).await {
+        Ok(samples) => Json(samples).into_response(),
+        Err(e) => e,
+    }
+}
+
+pub async fn download_json(
+    State(app): State<Arc<AppState>>,
+    Query(query): Query<HistoryQuery>,
+) -> impl IntoResponse {
+    match samples(&app, query).await {
+        Ok(samples) => (
+            [(
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"stats_history.json\"",
+            )],
+            Json(samples),
+        )
+            .into_response(),
+        Err(e) => e,
+    }
+}
+
+pub async fn download_csv(
+    State(app): State<Arc<AppState>>,
+    Query(query): Query<HistoryQuery>,
+) -> impl IntoResponse {
+    match samples(&app, query).await {
+        Ok(samples) => (
+            [
+                (header::CONTENT_TYPE, "text/csv"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"stats_history.csv\"",
+                ),
+            ],
+            to_csv(&samples),
+        )
+            .into_response(),
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+    use serde_json::json;
+
+    use super::*;
+
+    fn time(secs: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap() + Duration::seconds(secs)
+    }
+
+    fn sample(secs: i64) -> Value {
+        json!({"time": time(secs).to_rfc3339(), "tasks": secs})
+    }
+
+    async fn sink(kind: &str) -> (HistorySink, PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "tokio-web-demo-history-{}.{kind}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let config = Config {
+            stats_sink: Some(format!("{kind}:{}", path.display())),
+            ..Config::default()
+        };
+        let sink = HistorySink::from_config(&config).await.unwrap().unwrap();
+        (sink, path)
+    }
+
+    fn tasks(samples: &[Value]) -> Vec<i64> {
+        samples
+            .iter()
+            .map(|s| s["tasks"].as_i64().unwrap())
+            .collect()
+    }
+
+    async fn read_ranges(kind: &str) {
+        let (sink, path) = sink(kind).await;
+        for secs in 0..10 {
+            sink.write(&sample(secs)).await;
+        }
+        // written samples can be read back right away
+        assert_eq!(sink.read(None, None, 100).await.unwrap().len(), 10);
+        let samples = sink.read(Some(time(3)), Some(time(5)), 100).await.unwrap();
+        assert_eq!(tasks(&samples), [3, 4, 5]);
+        let samples = sink.read(Some(time(3)), None, 2).await.unwrap();
+        assert_eq!(tasks(&samples), [3, 4]);
+        // without a start the newest samples are kept, still in order
+        let samples = sink.read(None, None, 3).await.unwrap();
+        assert_eq!(tasks(&samples), [7, 8, 9]);
+        let samples = sink.read(None, Some(time(5)), 2).await.unwrap();
+        assert_eq!(tasks(&samples), [4, 5]);
+        let samples = sink.read(Some(time(20)), None, 100).await.unwrap();
+        assert!(samples.is_empty());
+        let samples = sink.read(Some(time(5)), Some(time(3)), 100).await.unwrap();
+        assert!(samples.is_empty());
+        drop(sink);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn jsonl_history_is_read_by_range() {
+        read_ranges("jsonl").await;
+    }
+
+    #[tokio::test]
+    async fn sqlite_history_is_read_by_range() {
+        read_ranges("sqlite").await;
+    }
+
+    #[tokio::test]
+    async fn unknown_sinks_are_rejected() {
+        let mut config = Config {
+            stats_sink: Some("csv:/tmp/stats.csv".into()),
+            ..Config::default()
+        };
+        assert!(HistorySink::from_config(&config).await.is_err());
+        config.stats_sink = None;
+        assert!(HistorySink::from_config(&config).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn inverted_ranges_are_rejected() {
+        let query = |from, to| HistoryQuery {
+            from: Some(time(from)),
+            to: Some(time(to)),
+        };
+        assert!(query(1, 2).validate().is_ok());
+        assert!(query(2, 2).validate().is_ok());
+        assert_eq!(query(3, 2).validate().unwrap_err().field, "from");
+        assert!(!in_range(&json!({"tasks": 1}), None, None));
+    }
+
+    #[test]
+    fn csv_has_a_column_per_field() {
+        let samples = [
+            json!({"time": "t1", "tasks": 1}),
+            json!({"time": "t2", "jobs": {"running": 2}, "note": "a, \"b\""}),
+        ];
+        assert_eq!(
+            to_csv(&samples),
+            "tasks,time,jobs,note\n\
+             1,t1,,\n\
+             ,t2,\"{\"\"running\"\":2}\",\"a, \"\"b\"\"\"\n"
+        );
+        assert_eq!(to_csv(&[]), "\n");
+    }
+}
@@ -0,0 +1,329 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::{sync::RwLock, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use crate::AppState;
+
+// finished jobs are kept for inspection, but only the most recent ones
+const JOB_HISTORY_MAX: usize = 256;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Finished,
+    Cancelled,
+    Failed { error: String },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub kind: &'static str,
+    pub params: Value,
+    pub started: DateTime<Utc>,
+    pub ended: Option<DateTime<Utc>>,
+    pub status: JobStatus,
+}
+
+struct Job {
+    info: JobInfo,
+    token: CancellationToken,
+}
+
+/// Keeps track of every load generator run started through the web interface.
+pub struct JobRegistry {
+    id_inc: AtomicU64,
+    jobs: RwLock<HashMap<u64, Job>>,
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self {
+            id_inc: AtomicU64::new(1),
+            jobs: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Registers a new job and drives `run` on a supervisor task. The future gets
+    /// the job's id and cancellation token and reports the outcome once all of its
+    /// spawned tasks are done.
+    pub async fn start<F, Fut>(
+        self: &Arc<Self>,
+        kind: &'static str,
+        params: Value,
+        run: F,
+    ) -> JobInfo
+    where
+        F: FnOnce(u64, CancellationToken) -> Fut,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let id = self.id_inc.fetch_add(1, Ordering::Relaxed);
+        let token = CancellationToken::new();
+        let info = JobInfo {
+            id,
+            kind,
+            params,
+            started: Utc::now(),
+            ended: None,
+            status: JobStatus::Running,
+        };
+        self.jobs.write().await.insert(
+            id,
+            Job {
+                info: info.clone(),
+                token: token.clone(),
+            },
+        );
+        log::info!("[jobs] started {kind} job #{id}");
+        // the job runs on a task of its own, so a panic ends up in the
+        // supervisor as a `JoinError` instead of taking the supervisor down
+        let job = tokio::spawn(run(id, token.clone()));
+        let registry = Arc::clone(self);
+        tokio::spawn(async move {
+            let status = match job.await {
+                Ok(Ok(())) if token.is_cancelled() => JobStatus::Cancelled,
+                Ok(Ok(())) => JobStatus::Finished,
+                Ok(Err(error)) => JobStatus::Failed { error },
+                Err(e) => JobStatus::Failed {
+                    error: format!("job panicked: {e}"),
+                },
+            };
+            registry.finish(id, status).await;
+        });
+        info
+    }
+
+    async fn finish(&self, id: u64, status: JobStatus) {
+        let mut jobs = self.jobs.write().await;
+        if let Some(job) = jobs.get_mut(&id) {
+            log::info!("[jobs] {} job #{id} ended: {:?}", job.info.kind, status);
+            job.info.ended = Some(Utc::now());
+            job.info.status = status;
+        }
+        // drop the oldest finished jobs
+        let mut finished: Vec<u64> = jobs
+            .values()
+            .filter(|job| job.info.ended.is_some())
+            .map(|job| job.info.id)
+            .collect();
+        if finished.len() > JOB_HISTORY_MAX {
+            finished.sort_unstable();
+            for id in &finished[..finished.len() - JOB_HISTORY_MAX] {
+                jobs.remove(id);
+            }
+        }
+    }
+
+    pub async fn list(&self) -> Vec<JobInfo> {
+        let jobs = self.jobs.read().await;
+        let sorted: BTreeMap<u64, JobInfo> = jobs
+            .iter()
+            .map(|(id, job)| (*id, job.info.clone()))
+            .collect();
+        sorted.into_values().collect()
+    }
+
+    pub async fn running(&self) -> usize {
+        let jobs = self.jobs.read().await;
+        jobs.values().filter(|job| job.info.ended.is_none()).count()
+    }
+
+    pub async fn get(&self, id: u64) -> Option<JobInfo> {
+        self.jobs.read().await.get(&id).map(|job| job.info.clone())
+    }
+
+    pub async fn cancel_all(&self) {
+        let jobs = self.jobs.read().await;
+        for job in jobs.values().filter(|job| job.info.ended.is_none()) {
+            job.token.cancel();
+        }
+    }
+
+    pub async fn wait_all(&self) {
+        while self.running().await > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Waits until the job has ended, returns `None` for unknown jobs.
+    pub async fn wait(&self, id: u64) -> Option<JobInfo> {
+        loop {
+            let job = self.get(id).await?;
+            if job.ended.is_some() {
+                return Some(job);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Signals a running job to stop. The status switches to cancelled as soon
+    /// as its tasks have returned.
+    pub async fn cancel(&self, id: u64) -> Option<JobInfo> {
+        let jobs = self.jobs.read().await;
+        let job = jobs.get(&id)?;
+        if job.info.ended.is_none() {
+            log::info!("[jobs] cancelling {} job #{id}", job.info.kind);
+            job.token.cancel();
+        }
+        Some(job.info.clone())
+    }
+}
+
+/// Result of a task spawned by a job, `()` for tasks which cannot fail.
+pub trait TaskResult {
+    fn into_result(self) -> Result<(), String>;
+}
+
+impl TaskResult for () {
+    fn into_result(self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl TaskResult for Result<(), String> {
+    fn into_result(self) -> Result<(), String> {
+        self
+    }
+}
+
+/// Waits for all handles of a job and turns errors and panics into a job
+/// failure, reporting the first error.
+pub async fn join_all<T: TaskResult>(handles: Vec<JoinHandle<T>>) -> Result<(), String> {
+    let mut failed = 0;
+    let mut first_error = None;
+    for handle in handles {
+        let error = match handle.await {
+            Ok(result) => match result.into_result() {
+                Ok(()) => continue,
+                Err(e) => e,
+            },
+            Err(e) => e.to_string(),
+        };
+        log::error!("[jobs] task failed: {error}");
+        failed += 1;
+        first_error.get_or_insert(error);
+    }
+    match first_error {
+        Some(error) => Err(format!("{failed} task(s) failed: {error}")),
+        None => Ok(()),
+    }
+}
+
+pub async fn list(State(app): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(app.jobs.list().await)
+}
+
+pub async fn get(State(app): State<Arc<AppState>>, Path(id): Path<u64>) -> impl IntoResponse {
+    match app.jobs.get(id).await {
+        Some(job) => Json(job).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+pub async fn cancel(State(app): State<Arc<AppState>>, Path(id): Path<u64>) -> impl IntoResponse {
+    match app.jobs.cancel(id).await {
+        Some(job) => Json(job).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    async fn run_job<Fut>(registry: &Arc<JobRegistry>, job: Fut) -> JobInfo
+    where
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let info = registry.start("test", json!({}), |_, _| job).await;
+        assert!(matches!(info.status, JobStatus::Running));
+        registry.wait(info.id).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn jobs_end_finished_or_failed() {
+        let registry = Arc::new(JobRegistry::new());
+        let job = run_job(&registry, async { Ok(()) }).await;
+        assert!(matches!(job.status, JobStatus::Finished));
+        assert!(job.ended.is_some());
+        let job = run_job(&registry, async { Err("no redis".into()) }).await;
+        assert!(matches!(job.status, JobStatus::Failed { error } if error == "no redis"));
+        assert_eq!(registry.running().await, 0);
+        assert_eq!(registry.list().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn panicking_job_fails() {
+        let registry = Arc::new(JobRegistry::new());
+        let job = run_job(&registry, async { panic!("boom") }).await;
+        assert!(matches!(job.status, JobStatus::Failed { error } if error.contains("panicked")));
+    }
+
+    #[tokio::test]
+    async fn cancelled_job_is_cancelled() {
+        let registry = Arc::new(JobRegistry::new());
+        let job = registry
+            .start("test", json!({}), |_, token| async move {
+                token.cancelled().await;
+                Ok(())
+            })
+            .await;
+        assert!(registry.cancel(job.id).await.is_some());
+        let job = registry.wait(job.id).await.unwrap();
+        assert!(matches!(job.status, JobStatus::Cancelled));
+        assert!(registry.cancel(u64::MAX).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn join_all_reports_failed_tasks() {
+        assert_eq!(join_all(vec![tokio::spawn(async {})]).await, Ok(()));
+        let handles = vec![
+            tokio::spawn(async { Ok(()) }),
+            tokio::spawn(async { Err("cannot connect".to_string()) }),
+            tokio::spawn(async { panic!("boom") }),
+        ];
+        let error = join_all(handles).await.unwrap_err();
+        assert!(
+            error.starts_with("2 task(s) failed: cannot connect"),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn only_recent_finished_jobs_are_kept() {
+        let registry = Arc::new(JobRegistry::new());
+        let mut last = 0;
+        for _ in 0..JOB_HISTORY_MAX + 2 {
+            last = registry
+                .start("test", json!({}), |_, _| async { Ok(()) })
+                .await
+                .id;
+        }
+        registry.wait(last).await.unwrap();
+        registry.wait_all().await;
+        let jobs = registry.list().await;
+        assert_eq!(jobs.len(), JOB_HISTORY_MAX);
+        assert!(registry.get(1).await.is_none());
+    }
+}
@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+
+use crate::generator::ValidationError;
+
+/// Allowed values for a single generator parameter. Each bound can be
+/// overridden with `TWD_LIMIT_<GENERATOR>_<FIELD>_MIN` / `_MAX`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Range {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl Range {
+    const fn new(min: u64, max: u64) -> Self {
+        Self { min, max }
+    }
+
+    pub fn check(&self, field: &'static str, value: u64) -> Result<(), ValidationError> {
+        if value < self.min || value > self.max {
+            return Err(ValidationError::new(
+                field,
+                format!(
+                    "{value} is out of range, must be between {} and {}",
+                    self.min, self.max
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SleeperLimits {
+    pub tasks: Range,
+    pub time: Range,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelLimits {
+    pub tasks: Range,
+    pub time: Range,
+    pub repeat: Range,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlockersLimits {
+    pub tasks: Range,
+    pub time: Range,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CpuLoadGenLimits {
+    pub threads: Range,
+    pub duration: Range,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisKeysLimits {
+    pub tasks: Range,
+    pub keys: Range,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub sleeper: SleeperLimits,
+    pub channel: ChannelLimits,
+    pub blockers: BlockersLimits,
+    pub cpuloadgen: CpuLoadGenLimits,
+    pub rediskeys: RedisKeysLimits,
+}
+
+impl Default for SleeperLimits {
+    fn default() -> Self {
+        Self {
+            tasks: Range::new(1, 1_000_000),
+            time: Range::new(1, 3600),
+        }
+    }
+}
+
+impl Default for ChannelLimits {
+    fn default() -> Self {
+        Self {
+            tasks: Range::new(1, 1_000_000),
+            time: Range::new(1, 60),
+            repeat: Range::new(1, 100),
+        }
+    }
+}
+
+impl Default for BlockersLimits {
+    fn default() -> Self {
+        Self {
+            tasks: Range::new(1, 1024),
+            time: Range::new(1, 60),
+        }
+    }
+}
+
+impl Default for CpuLoadGenLimits {
+    fn default() -> Self {
+        Self {
+            threads: Range::new(1, 96),
+            duration: Range::new(1, 600),
+        }
+    }
+}
+
+impl Default for RedisKeysLimits {
+    fn default() -> Self {
+        Self {
+            tasks: Range::new(1, 60),
+            keys: Range::new(1, 512_000),
+        }
+    }
+}
+
+impl Limits {
+    pub fn validate(&self) -> Result<(), String> {
+        let ranges = [
+            ("sleeper.tasks", self.sleeper.tasks),
+            ("sleeper.time", self.sleeper.time),
+            ("channel.tasks", self.channel.tasks),
+            ("channel.time", self.channel.time),
+            ("channel.repeat", self.channel.repeat),
+            ("blockers.tasks", self.blockers.tasks),
+            ("blockers.time", self.blockers.time),
+            ("cpuloadgen.threads", self.cpuloadgen.threads),
+            ("cpuloadgen.duration", self.cpuloadgen.duration),
+            ("rediskeys.tasks", self.rediskeys.tasks),
+            ("rediskeys.keys", self.rediskeys.keys),
+        ];
+        for (name, range) in ranges {
+            if range.min > range.max {
+                return Err(format!(
+                    "limit {name}: min {} is greater than max {}",
+                    range.min, range.max
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_are_inclusive() {
+        let range = Range::new(1, 60);
+        assert!(range.check("time", 1).is_ok());
+        assert!(range.check("time", 60).is_ok());
+        let e = range.check("time", 61).unwrap_err();
+        assert_eq!(e.field, "time");
+        assert_eq!(e.message, "61 is out of range, must be between 1 and 60");
+        assert!(range.check("time", 0).is_err());
+    }
+
+    #[test]
+    fn limits_are_read_partially_from_toml() {
+        let limits: Limits = toml::from_str("[blockers]\ntasks = { min = 2, max = 8 }").unwrap();
+        assert_eq!(limits.blockers.tasks.max, 8);
+        // everything else keeps its default
+        assert_eq!(limits.blockers.time.max, BlockersLimits::default().time.max);
+        assert_eq!(limits.sleeper.tasks.max, 1_000_000);
+        assert!(limits.validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_limits_are_rejected() {
+        let mut limits = Limits::default();
+        limits.channel.repeat = Range::new(10, 5);
+        assert_eq!(
+            limits.validate().unwrap_err(),
+            "limit channel.repeat: min 10 is greater than max 5"
+        );
+        assert!(toml::from_str::<Limits>("[sleeper]\nthreads = { min = 1, max = 2 }").is_err());
+        assert!(toml::from_str::<Limits>("[sleeper]\ntasks = { min = 1 }").is_err());
+    }
+}
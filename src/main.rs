@@ -1,39 +1,73 @@
+mod api;
+mod assets;
 mod blockers;
 mod channel;
 mod chat;
+mod config;
 mod cpu_loadgen;
+mod generator;
+mod history;
+mod jobs;
+mod limits;
+mod metric_source;
+mod metrics;
 mod rediskeys;
+mod scenario;
+mod shutdown;
 mod sleeper;
 mod soccer_field;
 mod stats_collector;
+mod templates;
 
 use axum::{
     extract::{ws::WebSocket, State, WebSocketUpgrade},
-    http::Response,
+    http::header,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
+use blockers::BlockersForm;
+use channel::ChannelForm;
 use chat::Chat;
-use minijinja::{context, path_loader};
+use config::Config;
+use cpu_loadgen::CpuLoadGenForm;
+use history::HistorySink;
+use jobs::JobRegistry;
+use minijinja::context;
+use rediskeys::RedisKeysForm;
+use scenario::{Scenario, ScenarioRunner};
 use serde_json::json;
-use soccer_field::SoccerFieldThread;
+use sleeper::SleeperForm;
+use soccer_field::Rooms;
 use stats_collector::StatsCollector;
-use std::{sync::Arc, time::Duration};
+use std::{future::IntoFuture, sync::Arc, time::Duration};
 use sysinfo::{System, SystemExt};
+use templates::{TemplateError, Templates};
+use tokio_util::sync::CancellationToken;
 
 async fn websocket_handler(
     ws: WebSocketUpgrade,
     State(app): State<Arc<AppState>>,
 ) -> impl IntoResponse {
     let stats_collector = Arc::clone(&app.stats);
-    ws.on_upgrade(|socket| handle_socket(socket, stats_collector))
+    let shutdown = app.shutdown.clone();
+    ws.on_upgrade(|socket| handle_socket(socket, stats_collector, shutdown))
 }
 
-async fn handle_socket(mut socket: WebSocket, stats_collector: Arc<StatsCollector>) {
+async fn handle_socket(
+    mut socket: WebSocket,
+    stats_collector: Arc<StatsCollector>,
+    shutdown: CancellationToken,
+) {
     let mut subscribe = stats_collector.subscribe();
     loop {
-        let result = subscribe.recv().await;
+        let result = tokio::select! {
+            result = subscribe.recv() => result,
+            _ = shutdown.cancelled() => {
+                let _ = socket.send(shutdown::close_message()).await;
+                break;
+            }
+        };
         match result {
             Ok(message) => {
                 if socket
@@ -44,9 +78,10 @@ async fn handle_socket(mut socket: WebSocket, stats_collector: Arc<StatsCollecto
                     break;
                 }
             }
-            Err(error) => {
-                log::error!("error receiving message from stats udpater: {:?}", error)
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("stats socket lagged behind by {n} messages");
             }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
         }
     }
 }
@@ -54,18 +89,17 @@ async fn handle_socket(mut socket: WebSocket, stats_collector: Arc<StatsCollecto
 fn main() {
     let _ = dotenv::dotenv();
     pretty_env_logger::init_timed();
-    let workers: usize = std::env::var("WORKER_THREADS")
-        .unwrap_or(String::from("1"))
-        .parse()
-        .unwrap();
-    let sync_workers: usize = std::env::var("SYNC_WORKER_THREADS")
-        .unwrap_or(String::from("1"))
-        .parse()
-        .unwrap();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("invalid configuration: {e}");
+            std::process::exit(2);
+        }
+    };
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
-        .worker_threads(workers)
-        .max_blocking_threads(sync_workers)
+        .worker_threads(config.worker_threads)
+        .max_blocking_threads(config.sync_worker_threads)
         .thread_keep_alive(Duration::from_secs(10))
         .on_thread_start(|| {
             log::info!("Started sync thread!");
@@ -76,79 +110,178 @@ fn main() {
         .build()
         .unwrap();
     rt.block_on(async {
-        let _ = async_main().await;
+        if let Err(e) = async_main(config).await {
+            log::error!("{e}");
+        }
     });
+    // blocking tasks which ignored the cancellation are left behind
+    rt.shutdown_timeout(Duration::from_secs(1));
 }
 
 struct AppState {
     stats: Arc<StatsCollector>,
     chat: Arc<Chat>,
-    soccer_thread: Arc<SoccerFieldThread>,
+    soccer_rooms: Option<Arc<Rooms>>,
+    jobs: Arc<JobRegistry>,
+    config: Arc<Config>,
+    scenarios: ScenarioRunner,
+    templates: Templates,
+    shutdown: CancellationToken,
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State for handler tests: no history, no stats sources and the soccer
+    /// rooms only if the feature is on.
+    fn for_tests(config: Config) -> Arc<Self> {
+        let config = Arc::new(config);
+        let soccer_rooms = config
+            .features
+            .soccer_field
+            .then(|| Arc::new(Rooms::new(Arc::clone(&config))));
+        Arc::new(AppState {
+            stats: Arc::new(StatsCollector::new(Duration::from_secs(1), 1, None, vec![])),
+            chat: Arc::new(Chat::new(1)),
+            soccer_rooms,
+            jobs: Arc::new(JobRegistry::new()),
+            templates: Templates::new(config.template_mode(), config.template_dir()).unwrap(),
+            config,
+            scenarios: ScenarioRunner::new(),
+            shutdown: CancellationToken::new(),
+        })
+    }
 }
 
-async fn async_main() -> Result<(), std::io::Error> {
+async fn async_main(config: Config) -> Result<(), std::io::Error> {
     console_subscriber::init();
+    let config = Arc::new(config);
     let chat = Arc::new(Chat::new(1000));
+    let sink = HistorySink::from_config(&config)
+        .await
+        .map_err(std::io::Error::other)?
+        .map(Arc::new);
+    let jobs = Arc::new(JobRegistry::new());
+    let soccer_rooms = config
+        .features
+        .soccer_field
+        .then(|| Arc::new(Rooms::new(Arc::clone(&config))));
     let stats = Arc::new(StatsCollector::new(
-        Duration::from_millis(updater_interval()),
-        message_count_max(),
+        config.stats_interval(),
+        config.ws_history_message_count_max,
+        sink,
+        metric_source::from_config(&config, Arc::clone(&jobs), soccer_rooms.clone()),
     ));
-    let mut minij = minijinja::Environment::new();
-    minij.set_loader(path_loader("templates"));
-    let soccer_thread = Arc::new(soccer_field::SoccerFieldThread::spawn());
+    let templates = Templates::new(config.template_mode(), config.template_dir())
+        .map_err(std::io::Error::other)?;
     let state = Arc::new(AppState {
         stats,
         chat,
-        soccer_thread,
+        soccer_rooms,
+        jobs,
+        config: Arc::clone(&config),
+        scenarios: ScenarioRunner::new(),
+        templates,
+        shutdown: CancellationToken::new(),
     });
     let chat = Router::new()
         .route("/", post(chat::chat))
         .route("/ws/:id", get(chat::websocket_handler))
         .with_state(Arc::clone(&state));
     let mut app = Router::new()
-        .nest_service("/static", tower_http::services::ServeDir::new("static"))
         .route("/", get(root))
-        .route("/soccer_field", get(soccer_field::get_field))
         .route("/stats/ws", get(websocket_handler))
-        .route("/sleeper", post(sleeper::sleeper))
-        .route("/channel", post(channel::channel))
-        .route("/soccer_field/ws", get(soccer_field::websocket_handler))
-        .with_state(state);
-    if chat_enabled() {
+        .route("/stats/history", get(history::history))
+        .route("/stats/history.json", get(history::download_json))
+        .route("/stats/history.csv", get(history::download_csv))
+        .route("/metrics", get(metrics::metrics))
+        .route("/config", get(config::get))
+        .route("/features", get(config::features))
+        .route("/sleeper", post(generator::form::<SleeperForm>))
+        .route("/channel", post(generator::form::<ChannelForm>))
+        .route("/jobs", get(jobs::list))
+        .route("/jobs/:id", get(jobs::get))
+        .route("/jobs/:id", delete(jobs::cancel))
+        .route("/scenarios/run", post(scenario::run))
+        .route("/scenarios/ws", get(scenario::websocket_handler))
+        .nest("/api/v1", api::router(&config));
+    app = assets::routes(app, &config);
+    let features = config.features;
+    if features.cpu_load {
+        app = app.route("/cpuloadgen", post(generator::form::<CpuLoadGenForm>));
+    }
+    if features.blockers {
+        app = app.route("/blockers", post(generator::form::<BlockersForm>));
+    }
+    if features.redis {
+        app = app.route("/rediskeys", post(generator::form::<RedisKeysForm>));
+    }
+    if features.soccer_field {
         app = app
-            .route("/cpuloadgen", post(cpu_loadgen::load_gen_threads))
-            .route("/blockers", post(blockers::blockers))
-            .route("/rediskeys", post(rediskeys::rediskeys));
+            .route("/soccer_field", get(soccer_field::get_field))
+            .route("/soccer_field/ws", get(soccer_field::websocket_handler))
+            .route(
+                "/soccer_field/ws/:room",
+                get(soccer_field::room_websocket_handler),
+            )
+            .route("/soccer_field/rooms", get(soccer_field::rooms))
+            .route("/soccer_field/replays", get(soccer_field::replays))
+            .route(
+                "/soccer_field/replays/:name/ws",
+                get(soccer_field::replay_websocket_handler),
+            )
+            .route("/soccer_field/bots", post(soccer_field::set_bots));
+    }
+    if let Some(path) = &config.scenario {
+        let scenario = Scenario::load(path).map_err(std::io::Error::other)?;
+        if let Err(e) = ScenarioRunner::run(&state, scenario).await {
+            log::error!("startup scenario rejected: {}: {}", e.field, e.message);
+        }
     }
-    if chat_enabled() {
+    let mut app = app.with_state(Arc::clone(&state));
+    if features.chat {
         app = app.nest("/chat", chat);
     }
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8123").await.unwrap();
-    log::info!("starting tokio web demo at http://127.0.0.1:8123");
-    axum::serve(listener, app).await
+    let address = config.address();
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .map_err(|e| std::io::Error::new(e.kind(), format!("cannot bind {address}: {e}")))?;
+    log::info!("starting tokio web demo at http://{address}");
+    let shutdown = state.shutdown.clone();
+    tokio::spawn(shutdown::signal(shutdown.clone()));
+    if let Some(rooms) = &state.soccer_rooms {
+        tokio::spawn(Arc::clone(rooms).reap_idle(shutdown.clone()));
+    }
+    let server = tokio::spawn(
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+            .into_future(),
+    );
+    shutdown.cancelled().await;
+    let deadline = Duration::from_secs(config.shutdown_timeout_secs);
+    let drain = async {
+        shutdown::drain(state, deadline).await;
+        server.await
+    };
+    match tokio::time::timeout(deadline, drain).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(std::io::Error::other(e)),
+        Err(_) => {
+            log::warn!("shutdown took longer than {deadline:?}, exiting anyway");
+            Ok(())
+        }
+    }
 }
 
-async fn root(State(app): State<Arc<AppState>>) -> impl IntoResponse {
-    // TODO: Temporary, has to be replaced with auto reloader somewhere
-    let mut minij = minijinja::Environment::new();
-    minij.set_loader(path_loader("templates"));
-    // TODO
+async fn root(State(app): State<Arc<AppState>>) -> Result<impl IntoResponse, TemplateError> {
     let ctx = context! {
-        chat => &chat_enabled(),
-        is_redis => &is_redis(),
+        features => &app.config.features,
         sysinfo => &get_systeminformation(),
         statsHistory => &serde_json::to_string(&app.stats.get_history().await).unwrap(),
-        messageCountMax => &message_count_max()
+        messageCountMax => &app.config.ws_history_message_count_max,
+        limits => &app.config.limits
     };
-    let rendered = minij
-        .get_template("base.html")
-        .unwrap()
-        .render(ctx)
-        .unwrap();
-    Response::builder()
-        .header("Cache-Control", "no-store")
-        .body(rendered)
-        .unwrap()
+    let rendered = app.templates.render("base.html", ctx)?;
+    Ok(([(header::CACHE_CONTROL, "no-store")], rendered))
 }
 
 fn get_systeminformation() -> serde_json::Value {
@@ -166,31 +299,3 @@ fn get_systeminformation() -> serde_json::Value {
         "spawned_tasks": metrics.spawned_tasks_count()
     })
 }
-
-fn message_count_max() -> usize {
-    std::env::var("WS_HISTORY_MESSAGE_COUNT_MAX")
-        .unwrap_or("2048".into())
-        .parse()
-        .unwrap_or(2048)
-}
-
-fn updater_interval() -> u64 {
-    std::env::var("WS_REFRESH_INTERVAL_MS")
-        .unwrap_or("1000".into())
-        .parse()
-        .unwrap_or(1000)
-}
-
-pub fn chat_enabled() -> bool {
-    matches!(
-        std::env::var("CHAT")
-            .map(|s| s.to_lowercase())
-            .unwrap_or("false".into())
-            .as_str(),
-        "true" | "1"
-    )
-}
-
-pub fn is_redis() -> bool {
-    std::env::var("REDIS_URL").is_ok()
-}
@@ -0,0 +1,439 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use redis::{InfoDict, RedisResult};
+use serde_json::{json, Map, Value};
+use sysinfo::{CpuExt, Pid, ProcessExt, SystemExt};
+use tokio::runtime::RuntimeMetrics;
+
+use crate::{config::Config, jobs::JobRegistry, soccer_field::Rooms};
+
+pub type Sample = Map<String, Value>;
+
+/// Something the stats collector samples periodically. The returned fields are
+/// merged into the stats message, so names have to be unique across sources.
+pub trait MetricSource: Send {
+    fn name(&self) -> &'static str;
+
+    fn sample(&mut self) -> BoxFuture<'_, Sample>;
+}
+
+/// A source as registered at the collector, sampled every `interval`.
+pub struct Registration {
+    pub source: Box<dyn MetricSource>,
+    pub interval: Duration,
+}
+
+impl Registration {
+    pub fn new(source: impl MetricSource + 'static, interval: Duration) -> Self {
+        Self {
+            source: Box::new(source),
+            interval,
+        }
+    }
+}
+
+fn object(value: Value) -> Sample {
+    match value {
+        Value::Object(map) => map,
+        _ => Sample::new(),
+    }
+}
+
+/// Names of all metric sources, in the order they are sampled.
+pub const SOURCES: &[&str] = &["runtime", "process", "system", "redis", "jobs", "soccer"];
+
+/// Builds the sources listed in `stats_sources`. Each source is sampled at its
+/// entry in `stats_interval_ms`, falling back to the collector's own interval.
+pub fn from_config(
+    config: &Config,
+    jobs: Arc<JobRegistry>,
+    soccer_rooms: Option<Arc<Rooms>>,
+) -> Vec<Registration> {
+    let interval = |name: &str| {
+        config
+            .stats_interval_ms
+            .get(name)
+            .copied()
+            .map(Duration::from_millis)
+            .unwrap_or(config.stats_interval())
+    };
+    let mut sources = vec![];
+    for name in config.stats_sources.iter().map(String::as_str) {
+        let source = match name {
+            "runtime" => Registration::new(RuntimeSource::new(), interval(name)),
+            "process" => Registration::new(ProcessSource::new(), interval(name)),
+            "system" => Registration::new(SystemSource::new(), interval(name)),
+            "redis" => match &config.redis_url {
+                Some(url) if config.features.redis => {
+                    Registration::new(RedisSource::new(url.clone()), interval(name))
+                }
+                _ => continue,
+            },
+            "jobs" => Registration::new(JobsSource::new(Arc::clone(&jobs)), interval(name)),
+            "soccer" => match &soccer_rooms {
+                Some(rooms) => {
+                    Registration::new(SoccerSource::new(Arc::clone(rooms)), interval(name))
+                }
+                None => continue,
+            },
+            // rejected when the config is validated
+            _ => continue,
+        };
+        sources.push(source);
+    }
+    sources
+}
+
+/// Counters of a worker thread at the previous tick. tokio only offers totals,
+/// the stats stream shows the change per tick.
+#[derive(Default)]
+struct WorkerCounters {
+    busy: Duration,
+    parks: u64,
+    polls: u64,
+    steals: u64,
+}
+
+/// Tasks, queue depths and per-worker activity of the runtime.
+pub struct RuntimeSource {
+    metrics: RuntimeMetrics,
+    last_sample: Instant,
+    workers: Vec<WorkerCounters>,
+    forced_yields: u64,
+}
+
+impl RuntimeSource {
+    pub fn new() -> Self {
+        let metrics = tokio::runtime::Handle::current().metrics();
+        let workers = (0..metrics.num_workers())
+            .map(|_| WorkerCounters::default())
+            .collect();
+        Self {
+            metrics,
+            last_sample: Instant::now(),
+            workers,
+            forced_yields: 0,
+        }
+    }
+
+    #[cfg(tokio_unstable)]
+    fn runtime(&mut self) -> Value {
+        let metrics = &self.metrics;
+        let elapsed = self.last_sample.elapsed();
+        self.last_sample = Instant::now();
+        let workers: Vec<Value> = self
+            .workers
+            .iter_mut()
+            .enumerate()
+            .map(|(worker, last)| {
+                let current = WorkerCounters {
+                    busy: metrics.worker_total_busy_duration(worker),
+                    parks: metrics.worker_park_count(worker),
+                    polls: metrics.worker_poll_count(worker),
+                    steals: metrics.worker_steal_count(worker),
+                };
+                let busy = (current.busy - last.busy).as_secs_f64() / elapsed.as_secs_f64();
+                let sample = json!({
+                    "busy": ((busy * 1000.0).round() / 10.0).min(100.0),
+                    "parks": current.parks - last.parks,
+                    "polls": current.polls - last.polls,
+                    "steals": current.steals - last.steals,
+                    "queue": metrics.worker_local_queue_depth(worker),
+                });
+                *last = current;
+                sample
+            })
+            .collect();
+        let forced_yields = metrics.budget_forced_yield_count();
+        let sample = json!({
+            "tasks": metrics.num_alive_tasks(),
+            "sync_threads": metrics.num_blocking_threads(),
+            "global_queue": metrics.global_queue_depth(),
+            "blocking_queue": metrics.blocking_queue_depth(),
+            "forced_yields": forced_yields - self.forced_yields,
+            "workers": workers,
+        });
+        self.forced_yields = forced_yields;
+        sample
+    }
+
+    // without tokio_unstable only a few metrics are available
+    #[cfg(not(tokio_unstable))]
+    fn runtime(&mut self) -> Value {
+        json!({
+            "tasks": self.metrics.num_alive_tasks(),
+            "global_queue": self.metrics.global_queue_depth(),
+        })
+    }
+}
+
+impl MetricSource for RuntimeSource {
+    fn name(&self) -> &'static str {
+        "runtime"
+    }
+
+    fn sample(&mut self) -> BoxFuture<'_, Sample> {
+        futures::future::ready(object(self.runtime())).boxed()
+    }
+}
+
+/// Memory and CPU usage of the server process.
+pub struct ProcessSource {
+    system: sysinfo::System,
+    pid: Pid,
+}
+
+impl ProcessSource {
+    pub fn new() -> Self {
+        let pid = sysinfo::get_current_pid().expect("cannot get pid");
+        let mut system = sysinfo::System::new();
+        system.refresh_cpu();
+        system.refresh_process(pid);
+        Self { system, pid }
+    }
+}
+
+impl MetricSource for ProcessSource {
+    fn name(&self) -> &'static str {
+        "process"
+    }
+
+    fn sample(&mut self) -> BoxFuture<'_, Sample> {
+        self.system.refresh_process(self.pid);
+        let cpus = self.system.cpus().len().max(1) as f32;
+        let sample = match self.system.process(self.pid) {
+            Some(process) => json!({
+                "mem_proc": process.memory() / (1024 * 1024),
+                "cpu_proc": ((process.cpu_usage() / cpus) * 1000.0).round() / 1000.0,
+            }),
+            None => {
+                log::error!("cannot get current process from system");
+                json!({})
+            }
+        };
+        futures::future::ready(object(sample)).boxed()
+    }
+}
+
+/// Memory and CPU usage of the whole system.
+pub struct SystemSource {
+    system: sysinfo::System,
+}
+
+impl SystemSource {
+    pub fn new() -> Self {
+        let mut system = sysinfo::System::new();
+        system.refresh_cpu();
+        system.refresh_memory();
+        Self { system }
+    }
+}
+
+impl MetricSource for SystemSource {
+    fn name(&self) -> &'static str {
+        "system"
+    }
+
+    fn sample(&mut self) -> BoxFuture<'_, Sample> {
+        self.system.refresh_cpu();
+        self.system.refresh_memory();
+        let sample = json!({
+            "mem": self.system.used_memory() / (1024 * 1024),
+            "cpu": self.system.global_cpu_info().cpu_usage(),
+        });
+        futures::future::ready(object(sample)).boxed()
+    }
+}
+
+/// Number of keys in redis database 0, reconnects if the connection is lost.
+pub struct RedisSource {
+    url: String,
+    conn: Option<redis::aio::Connection>,
+}
+
+impl RedisSource {
+    pub fn new(url: String) -> Self {
+        Self { url, conn: None }
+    }
+
+    async fn keys(&mut self) -> u64 {
+        if self.conn.is_none() {
+            let conn = match redis::Client::open(self.url.as_str()) {
+                Ok(client) => client.get_async_connection().await,
+                Err(e) => Err(e),
+            };
+            match conn {
+                Ok(conn) => self.conn = Some(conn),
+                Err(e) => {
+                    log::warn!("cannot connect to redis for metrics: {e}");
+                    return 0;
+                }
+            }
+        }
+        let Some(conn) = &mut self.conn else {
+            return 0;
+        };
+        let response: RedisResult<InfoDict> =
+            redis::cmd("INFO").arg("KEYSPACE").query_async(conn).await;
+        if response.is_err() {
+            self.conn = None;
+        }
+        get_redis_keys_from_result(&response)
+    }
+}
+
+impl MetricSource for RedisSource {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn sample(&mut self) -> BoxFuture<'_, Sample> {
+        async move { object(json!({ "keys": self.keys().await })) }.boxed()
+    }
+}
+
+/// Load generator jobs of the demo itself.
+pub struct JobsSource {
+    jobs: Arc<JobRegistry>,
+}
+
+impl JobsSource {
+    pub fn new(jobs: Arc<JobRegistry>) -> Self {
+        Self { jobs }
+    }
+}
+
+impl MetricSource for JobsSource {
+    fn name(&self) -> &'static str {
+        "jobs"
+    }
+
+    fn sample(&mut self) -> BoxFuture<'_, Sample> {
+        async move { object(json!({ "jobs": self.jobs.running().await })) }.boxed()
+    }
+}
+
+/// Tick timing of the soccer field simulations.
+pub struct SoccerSource {
+    rooms: Arc<Rooms>,
+}
+
+impl SoccerSource {
+    pub fn new(rooms: Arc<Rooms>) -> Self {
+        Self { rooms }
+    }
+}
+
+impl MetricSource for SoccerSource {
+    fn name(&self) -> &'static str {
+        "soccer"
+    }
+
+    fn sample(&mut self) -> BoxFuture<'_, Sample> {
+        let sample = json!({ "soccer": self.rooms.telemetry() });
+        futures::future::ready(object(sample)).boxed()
+    }
+}
+
+fn get_redis_keys_from_result(response: &RedisResult<InfoDict>) -> u64 {
+    if let Ok(response) = response {
+        let db0: Option<String> = response.get("db0");
+        if let Some(db0) = db0 {
+            // unwraps "keys=123,bla=123,blabla=123..."
+            let x: Option<u64> = db0
+                .split_once(',')
+                .map(|(x, _)| x.split_once('=').map(|(_, x)| x.parse().ok()))
+                .unwrap_or(Some(Some(0)))
+                .unwrap_or(Some(0));
+            x.unwrap_or(0)
+        } else {
+            0
+        }
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn sources_follow_the_config() {
+        let config = Config {
+            stats_sources: vec!["jobs".into(), "redis".into(), "runtime".into()],
+            stats_interval_ms: BTreeMap::from([("jobs".to_string(), 5000)]),
+            ..Config::default()
+        };
+        let sources = from_config(&config, Arc::new(JobRegistry::new()), None);
+        // redis is skipped without a redis_url
+        let names: Vec<&str> = sources.iter().map(|r| r.source.name()).collect();
+        assert_eq!(names, ["jobs", "runtime"]);
+        assert_eq!(sources[0].interval, Duration::from_secs(5));
+        assert_eq!(sources[1].interval, config.stats_interval());
+        // soccer only samples open rooms
+        let config = Config {
+            stats_sources: vec!["soccer".into()],
+            ..Config::default()
+        };
+        assert!(from_config(&config, Arc::new(JobRegistry::new()), None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn samples_are_merged_by_field() {
+        let jobs = Arc::new(JobRegistry::new());
+        let mut source = JobsSource::new(Arc::clone(&jobs));
+        assert_eq!(source.sample().await, object(json!({"jobs": 0})));
+        let mut runtime = RuntimeSource::new();
+        let sample = runtime.sample().await;
+        assert!(sample["tasks"].is_u64());
+        assert!(sample["global_queue"].is_u64());
+        assert!(object(json!([1, 2])).is_empty());
+    }
+
+    #[cfg(tokio_unstable)]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn worker_counters_are_deltas() {
+        let polls = |sample: &Sample| -> u64 {
+            let workers = sample["workers"].as_array().unwrap();
+            assert_eq!(workers.len(), 2);
+            for worker in workers {
+                let busy = worker["busy"].as_f64().unwrap();
+                assert!((0.0..=100.0).contains(&busy), "{busy}");
+            }
+            workers.iter().map(|w| w["polls"].as_u64().unwrap()).sum()
+        };
+        let mut source = RuntimeSource::new();
+        let tasks: Vec<_> = (0..1000).map(|_| tokio::spawn(async {})).collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let first = polls(&source.sample().await);
+        // workers publish their counters in batches, so not all 1000 may show
+        assert!(first > 100, "{first}");
+        // hardly anything ran since, a running total would be larger
+        let second = polls(&source.sample().await);
+        assert!(second < first, "{second} >= {first}");
+        let metrics = tokio::runtime::Handle::current().metrics();
+        let total: u64 = (0..2).map(|w| metrics.worker_poll_count(w)).sum();
+        assert!(first + second <= total);
+    }
+
+    #[test]
+    fn redis_keys_are_read_from_the_keyspace() {
+        let info = |text: &str| Ok(InfoDict::new(text));
+        assert_eq!(
+            get_redis_keys_from_result(&info("db0:keys=123,expires=0,avg_ttl=0")),
+            123
+        );
+        assert_eq!(get_redis_keys_from_result(&info("")), 0);
+        let error = redis::RedisError::from((redis::ErrorKind::IoError, "gone"));
+        assert_eq!(get_redis_keys_from_result(&Err(error)), 0);
+    }
+}
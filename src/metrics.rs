@@ -0,0 +1,255 @@
+use std::{fmt::Display, fmt::Write, sync::Arc};
+
+use axum::{extract::State, http::header, response::IntoResponse};
+use serde_json::Value;
+use tokio::runtime::RuntimeMetrics;
+
+use crate::AppState;
+
+const PREFIX: &str = "tokio_web_demo";
+
+/// Prometheus text exposition format, see
+/// https://prometheus.io/docs/instrumenting/exposition_formats/
+struct Exposition(String);
+
+impl Exposition {
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {PREFIX}_{name} {help}");
+        let _ = writeln!(self.0, "# TYPE {PREFIX}_{name} {kind}");
+    }
+
+    fn metric(&mut self, name: &str, kind: &str, help: &str, value: impl Display) {
+        self.header(name, kind, help);
+        let _ = writeln!(self.0, "{PREFIX}_{name} {value}");
+    }
+
+    /// One series per worker thread, labeled with the worker index.
+    #[cfg(tokio_unstable)]
+    fn per_worker<V: Display>(
+        &mut self,
+        name: &str,
+        kind: &str,
+        help: &str,
+        workers: usize,
+        value: impl Fn(usize) -> V,
+    ) {
+        self.header(name, kind, help);
+        for worker in 0..workers {
+            let _ = writeln!(
+                self.0,
+                "{PREFIX}_{name}{{worker=\"{worker}\"}} {}",
+                value(worker)
+            );
+        }
+    }
+
+    /// Runtime wide counters and gauges, per worker with `tokio_unstable`.
+    fn runtime(&mut self, metrics: &RuntimeMetrics) {
+        let workers = metrics.num_workers();
+        self.metric(
+            "runtime_workers",
+            "gauge",
+            "Number of runtime worker threads",
+            workers,
+        );
+        self.metric(
+            "runtime_alive_tasks",
+            "gauge",
+            "Number of alive tasks",
+            metrics.num_alive_tasks(),
+        );
+        self.metric(
+            "runtime_global_queue_depth",
+            "gauge",
+            "Tasks in the global queue",
+            metrics.global_queue_depth(),
+        );
+        #[cfg(tokio_unstable)]
+        {
+            self.metric(
+                "runtime_blocking_threads",
+                "gauge",
+                "Number of blocking threads",
+                metrics.num_blocking_threads(),
+            );
+            self.metric(
+                "runtime_idle_blocking_threads",
+                "gauge",
+                "Number of idle blocking threads",
+                metrics.num_idle_blocking_threads(),
+            );
+            self.metric(
+                "runtime_blocking_queue_depth",
+                "gauge",
+                "Tasks waiting for a blocking thread",
+                metrics.blocking_queue_depth(),
+            );
+            self.metric(
+                "runtime_spawned_tasks_total",
+                "counter",
+                "Tasks spawned since the runtime started",
+                metrics.spawned_tasks_count(),
+            );
+            self.metric(
+                "runtime_remote_schedule_total",
+                "counter",
+                "Tasks scheduled from outside of the runtime",
+                metrics.remote_schedule_count(),
+            );
+            self.metric(
+                "runtime_budget_forced_yield_total",
+                "counter",
+                "Times a task was forced to yield after exhausting its budget",
+                metrics.budget_forced_yield_count(),
+            );
+            self.metric(
+                "runtime_io_driver_ready_total",
+                "counter",
+                "Ready events processed by the IO driver",
+                metrics.io_driver_ready_count(),
+            );
+            self.per_worker(
+                "runtime_worker_park_total",
+                "counter",
+                "Times a worker thread parked",
+                workers,
+                |w| metrics.worker_park_count(w),
+            );
+            self.per_worker(
+                "runtime_worker_poll_total",
+                "counter",
+                "Tasks polled by a worker thread",
+                workers,
+                |w| metrics.worker_poll_count(w),
+            );
+            self.per_worker(
+                "runtime_worker_steal_total",
+                "counter",
+                "Tasks stolen by a worker thread",
+                workers,
+                |w| metrics.worker_steal_count(w),
+            );
+            self.per_worker(
+                "runtime_worker_busy_seconds_total",
+                "counter",
+                "Time a worker thread spent busy",
+                workers,
+                |w| metrics.worker_total_busy_duration(w).as_secs_f64(),
+            );
+            self.per_worker(
+                "runtime_worker_local_queue_depth",
+                "gauge",
+                "Tasks in the local queue of a worker thread",
+                workers,
+                |w| metrics.worker_local_queue_depth(w),
+            );
+        }
+    }
+
+    /// Takes a value of the latest stats sample, if it was collected.
+    fn sample(&mut self, sample: &Value, field: &str, name: &str, help: &str) {
+        if let Some(value) = sample.get(field).filter(|v| v.is_number()) {
+            self.metric(name, "gauge", help, value);
+        }
+    }
+}
+
+pub async fn metrics(State(app): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut out = Exposition(String::new());
+
+    // values of the stats collector
+    if let Some(sample) = app.stats.latest().await {
+        out.sample(
+            &sample,
+            "mem",
+            "system_memory_used_megabytes",
+            "Used system memory",
+        );
+        out.sample(
+            &sample,
+            "mem_proc",
+            "process_memory_megabytes",
+            "Memory of the server process",
+        );
+        out.sample(
+            &sample,
+            "cpu",
+            "system_cpu_usage_percent",
+            "CPU usage of the whole system",
+        );
+        out.sample(
+            &sample,
+            "cpu_proc",
+            "process_cpu_usage_percent",
+            "CPU usage of the server process",
+        );
+        out.sample(&sample, "keys", "redis_keys", "Keys in redis database 0");
+    }
+
+    // runtime metrics, read at scrape time
+    out.runtime(&tokio::runtime::Handle::current().metrics());
+
+    out.metric(
+        "jobs_running",
+        "gauge",
+        "Load generator jobs currently running",
+        app.jobs.running().await,
+    );
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Checks the text format: every series is announced by `# HELP` and
+    /// `# TYPE` and has a numeric value.
+    fn assert_valid(text: &str) {
+        let mut announced = vec![];
+        for line in text.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                let mut parts = comment.splitn(3, ' ');
+                let (kind, name) = (parts.next().unwrap(), parts.next().unwrap());
+                assert!(kind == "HELP" || kind == "TYPE", "{line}");
+                assert!(name.starts_with(PREFIX), "{line}");
+                announced.push(name.to_string());
+                continue;
+            }
+            let (series, value) = line.rsplit_once(' ').expect(line);
+            let name = series.split('{').next().unwrap();
+            assert!(announced.iter().any(|a| a == name), "{line}");
+            assert!(value.parse::<f64>().is_ok(), "{line}");
+        }
+    }
+
+    #[test]
+    fn samples_become_gauges() {
+        let mut out = Exposition(String::new());
+        let sample = json!({"cpu": 12.5, "keys": "n/a"});
+        out.sample(&sample, "cpu", "system_cpu_usage_percent", "CPU usage");
+        // missing and non-numeric values are left out
+        out.sample(&sample, "mem", "system_memory_used_megabytes", "Memory");
+        out.sample(&sample, "keys", "redis_keys", "Keys");
+        assert_eq!(
+            out.0,
+            "# HELP tokio_web_demo_system_cpu_usage_percent CPU usage\n\
+             # TYPE tokio_web_demo_system_cpu_usage_percent gauge\n\
+             tokio_web_demo_system_cpu_usage_percent 12.5\n"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn runtime_metrics_are_valid_exposition() {
+        let mut out = Exposition(String::new());
+        out.runtime(&tokio::runtime::Handle::current().metrics());
+        assert_valid(&out.0);
+        assert!(out.0.contains("\ntokio_web_demo_runtime_workers 2\n"));
+        #[cfg(tokio_unstable)]
+        assert!(out
+            .0
+            .contains("tokio_web_demo_runtime_worker_poll_total{worker=\"1\"}"));
+    }
+}
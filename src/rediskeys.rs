@@ -1,80 +1,104 @@
-use axum::Form;
-use redis::{AsyncCommands, RedisResult};
-use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+
+use redis::{aio::Connection, AsyncCommands, RedisResult};
+use serde::{Deserialize, Serialize};
 use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::Config,
+    generator::{self, LoadGenerator, ValidationError},
+    jobs,
+    limits::Limits,
+};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct RedisKeysForm {
     tasks: u64,
     keys: u64,
-    delete: Option<String>,
+    #[serde(default, deserialize_with = "generator::checkbox")]
+    delete: bool,
 }
 
-impl RedisKeysForm {
-    pub fn delete(&self) -> bool {
-        let Some(delete) = &self.delete else {
-            return false;
-        };
-        matches!(delete.to_lowercase().as_str(), "on" | "true" | "1")
-    }
-}
+impl LoadGenerator for RedisKeysForm {
+    const KIND: &'static str = "rediskeys";
 
-pub async fn rediskeys(Form(f): Form<RedisKeysForm>) {
-    let mut handles: Vec<JoinHandle<()>> = vec![];
-    for task_nr in 0..f.tasks {
-        let keys = f.keys;
-        let handle = tokio::spawn(async move {
-            log::debug!("task {} inserting {} keys.", task_nr, keys);
-            let url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1:6379".into());
-            let client = redis::Client::open(url).expect("cannot create redis client");
-            let mut con = client
-                .get_async_connection()
-                .await
-                .expect("cannot get async connection");
-            for key_nr in 0..keys {
-                let key = format!("{task_nr}:{key_nr}");
-                let _: RedisResult<String> = con.set(key.as_str(), key.as_str()).await;
-            }
-            log::debug!("task {} done inserting", task_nr);
-        });
-        handles.push(handle);
-    }
-    for handle in handles.into_iter() {
-        let _ = handle.await;
+    fn validate(&self, limits: &Limits) -> Result<(), ValidationError> {
+        limits.rediskeys.tasks.check("tasks", self.tasks)?;
+        limits.rediskeys.keys.check("keys", self.keys)
     }
-    let mut handles: Vec<JoinHandle<()>> = vec![];
-    log::debug!(
-        "done inserting {} keys with {} workers.",
-        f.keys * f.tasks,
-        f.tasks
-    );
-    if !f.delete() {
-        return;
-    }
-    for task_nr in 0..f.tasks {
-        let keys = f.keys;
-        let handle = tokio::spawn(async move {
-            log::debug!("task {} deleting {} keys.", task_nr, keys);
-            let url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1:6379".into());
-            let client = redis::Client::open(url).expect("cannot create redis client");
-            let mut con = client
-                .get_async_connection()
-                .await
-                .expect("cannot get async connection");
-            for key_nr in 0..keys {
-                let key = format!("{task_nr}:{key_nr}");
-                let _: RedisResult<String> = con.get_del(key.as_str()).await;
-            }
-            log::debug!("task {} done deleting", task_nr);
-        });
-        handles.push(handle);
+
+    // depends on the redis server
+    fn estimated_duration(&self, _config: &Config) -> Option<Duration> {
+        None
     }
-    for handle in handles.into_iter() {
-        let _ = handle.await;
+
+    async fn run(self, config: Arc<Config>, token: CancellationToken) -> Result<(), String> {
+        let url = config
+            .redis_url
+            .clone()
+            .unwrap_or("redis://127.0.0.1:6379".into());
+        let mut handles: Vec<JoinHandle<Result<(), String>>> = vec![];
+        for task_nr in 0..self.tasks {
+            let keys = self.keys;
+            let url = url.clone();
+            let token = token.clone();
+            let handle = tokio::spawn(async move {
+                log::debug!("task {} inserting {} keys.", task_nr, keys);
+                let mut con = connect(url).await?;
+                for key_nr in 0..keys {
+                    if token.is_cancelled() {
+                        break;
+                    }
+                    let key = format!("{task_nr}:{key_nr}");
+                    let _: RedisResult<String> = con.set(key.as_str(), key.as_str()).await;
+                }
+                log::debug!("task {} done inserting", task_nr);
+                Ok(())
+            });
+            handles.push(handle);
+        }
+        jobs::join_all(handles).await?;
+        log::debug!(
+            "done inserting {} keys with {} workers.",
+            self.keys.saturating_mul(self.tasks),
+            self.tasks
+        );
+        // deleting still runs after a cancel, so no keys are left behind
+        if !self.delete {
+            return Ok(());
+        }
+        let mut handles: Vec<JoinHandle<Result<(), String>>> = vec![];
+        for task_nr in 0..self.tasks {
+            let keys = self.keys;
+            let url = url.clone();
+            let handle = tokio::spawn(async move {
+                log::debug!("task {} deleting {} keys.", task_nr, keys);
+                let mut con = connect(url).await?;
+                for key_nr in 0..keys {
+                    let key = format!("{task_nr}:{key_nr}");
+                    let _: RedisResult<String> = con.get_del(key.as_str()).await;
+                }
+                log::debug!("task {} done deleting", task_nr);
+                Ok(())
+            });
+            handles.push(handle);
+        }
+        jobs::join_all(handles).await?;
+        log::debug!(
+            "done deleting {} keys with {} workers.",
+            self.keys.saturating_mul(self.tasks),
+            self.tasks
+        );
+        Ok(())
     }
-    log::debug!(
-        "done deleting {} keys with {} workers.",
-        f.keys * f.tasks,
-        f.tasks
-    );
+}
+
+async fn connect(url: String) -> Result<Connection, String> {
+    let client =
+        redis::Client::open(url).map_err(|e| format!("cannot create redis client: {e}"))?;
+    client
+        .get_async_connection()
+        .await
+        .map_err(|e| format!("cannot connect to redis: {e}"))
 }
@@ -0,0 +1,444 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{ws::WebSocket, State, WebSocketUpgrade},
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    blockers::BlockersForm,
+    channel::ChannelForm,
+    config::Config,
+    cpu_loadgen::CpuLoadGenForm,
+    generator::{self, LoadGenerator, Started, ValidationError},
+    jobs::JobInfo,
+    rediskeys::RedisKeysForm,
+    shutdown,
+    sleeper::SleeperForm,
+    AppState,
+};
+
+/// A list of generator runs, loaded from a TOML or JSON file:
+///
+/// ```toml
+/// name = "starve the workers"
+///
+/// [[steps]]
+/// generator = "sleeper"
+/// tasks = 10000
+/// time = 30
+///
+/// [[steps]]
+/// delay = 5
+/// wait = true
+/// generator = "blockers"
+/// tasks = 4
+/// time = 10
+/// ```
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Step {
+    /// seconds to wait before the step is started
+    #[serde(default)]
+    delay: u64,
+    /// wait for the started job to end before moving on
+    #[serde(default)]
+    wait: bool,
+    #[serde(flatten)]
+    generator: StepGenerator,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "generator", rename_all = "lowercase")]
+enum StepGenerator {
+    Sleeper(SleeperForm),
+    Channel(ChannelForm),
+    Blockers(BlockersForm),
+    CpuLoadGen(CpuLoadGenForm),
+    RedisKeys(RedisKeysForm),
+}
+
+impl StepGenerator {
+    fn kind(&self) -> &'static str {
+        match self {
+            StepGenerator::Sleeper(_) => SleeperForm::KIND,
+            StepGenerator::Channel(_) => ChannelForm::KIND,
+            StepGenerator::Blockers(_) => BlockersForm::KIND,
+            StepGenerator::CpuLoadGen(_) => CpuLoadGenForm::KIND,
+            StepGenerator::RedisKeys(_) => RedisKeysForm::KIND,
+        }
+    }
+
+    fn validate(&self, config: &Config) -> Result<(), ValidationError> {
+        if !config.features.generator(self.kind()) {
+            return Err(ValidationError::new(
+                "generator",
+                format!("'{}' is not enabled", self.kind()),
+            ));
+        }
+        let limits = &config.limits;
+        match self {
+            StepGenerator::Sleeper(g) => g.validate(limits),
+            StepGenerator::Channel(g) => g.validate(limits),
+            StepGenerator::Blockers(g) => g.validate(limits),
+            StepGenerator::CpuLoadGen(g) => g.validate(limits),
+            StepGenerator::RedisKeys(g) => g.validate(limits),
+        }
+    }
+
+    async fn start(self, app: &Arc<AppState>) -> Result<Started, ValidationError> {
+        match self {
+            StepGenerator::Sleeper(g) => generator::start(app, g).await,
+            StepGenerator::Channel(g) => generator::start(app, g).await,
+            StepGenerator::Blockers(g) => generator::start(app, g).await,
+            StepGenerator::CpuLoadGen(g) => generator::start(app, g).await,
+            StepGenerator::RedisKeys(g) => generator::start(app, g).await,
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read scenario {}: {e}", path.display()))?;
+        let is_toml = path.extension().is_some_and(|ext| ext == "toml");
+        Self::parse(&content, is_toml)
+            .map_err(|e| format!("cannot parse scenario {}: {e}", path.display()))
+    }
+
+    fn parse(content: &str, is_toml: bool) -> Result<Self, String> {
+        let (scenario, raw): (Self, Value) = if is_toml {
+            let scenario = toml::from_str(content).map_err(|e| e.to_string())?;
+            (
+                scenario,
+                toml::from_str(content).map_err(|e| e.to_string())?,
+            )
+        } else {
+            let scenario = serde_json::from_str(content).map_err(|e| e.to_string())?;
+            (
+                scenario,
+                serde_json::from_str(content).map_err(|e| e.to_string())?,
+            )
+        };
+        scenario.check_keys(&raw)?;
+        Ok(scenario)
+    }
+
+    /// `deny_unknown_fields` doesn't work with the flattened generator, so a
+    /// typo like `dealy = 5` is caught by comparing each step with the keys
+    /// it was parsed into.
+    fn check_keys(&self, raw: &Value) -> Result<(), String> {
+        let raw_steps = raw["steps"].as_array().into_iter().flatten();
+        for (i, (step, raw_step)) in self.steps.iter().zip(raw_steps).enumerate() {
+            let generator = serde_json::to_value(&step.generator).unwrap_or_default();
+            let known = |key: &str| key == "delay" || key == "wait" || generator.get(key).is_some();
+            let raw_keys = raw_step.as_object().into_iter().flat_map(|o| o.keys());
+            if let Some(key) = raw_keys.into_iter().find(|key| !known(key)) {
+                return Err(format!(
+                    "step {}: unknown field '{key}' for {}",
+                    i + 1,
+                    step.generator.kind()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks every step up front, so a scenario doesn't fail half way through.
+    fn validate(&self, config: &Config) -> Result<(), ValidationError> {
+        for (i, step) in self.steps.iter().enumerate() {
+            step.generator.validate(config).map_err(|e| {
+                ValidationError::new(e.field, format!("step {}: {}", i + 1, e.message))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ScenarioEvent {
+    Started {
+        scenario: u64,
+        name: String,
+        steps: usize,
+    },
+    Waiting {
+        scenario: u64,
+        step: usize,
+        delay: u64,
+    },
+    Step {
+        scenario: u64,
+        step: usize,
+        job: JobInfo,
+    },
+    StepEnded {
+        scenario: u64,
+        step: usize,
+        job: JobInfo,
+    },
+    Failed {
+        scenario: u64,
+        step: usize,
+        error: String,
+    },
+    Finished {
+        scenario: u64,
+    },
+    Cancelled {
+        scenario: u64,
+    },
+}
+
+/// Runs scenarios as jobs and publishes their progress.
+pub struct ScenarioRunner {
+    events: broadcast::Sender<ScenarioEvent>,
+}
+
+impl ScenarioRunner {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self { events }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ScenarioEvent> {
+        self.events.subscribe()
+    }
+
+    /// Validates the scenario and starts it as a job of its own.
+    pub async fn run(app: &Arc<AppState>, scenario: Scenario) -> Result<JobInfo, ValidationError> {
+        scenario.validate(&app.config)?;
+        let params = serde_json::to_value(&scenario).unwrap_or_default();
+        let runner_app = Arc::clone(app);
+        let job = app
+            .jobs
+            .start("scenario", params, |id, token| {
+                Self::steps(runner_app, id, scenario, token)
+            })
+            .await;
+        Ok(job)
+    }
+
+    async fn steps(
+        app: Arc<AppState>,
+        id: u64,
+        scenario: Scenario,
+        token: CancellationToken,
+    ) -> Result<(), String> {
+        let events = &app.scenarios.events;
+        let send = |event: ScenarioEvent| {
+            log::debug!("[scenario] {:?}", event);
+            let _ = events.send(event);
+        };
+        log::info!("[scenario] running '{}' as job #{id}", scenario.name);
+        send(ScenarioEvent::Started {
+            scenario: id,
+            name: scenario.name,
+            steps: scenario.steps.len(),
+        });
+        let mut started: Vec<u64> = vec![];
+        for (i, step) in scenario.steps.into_iter().enumerate() {
+            let step_nr = i + 1;
+            if step.delay > 0 {
+                send(ScenarioEvent::Waiting {
+                    scenario: id,
+                    step: step_nr,
+                    delay: step.delay,
+                });
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(step.delay)) => {}
+                _ = token.cancelled() => break,
+            }
+            let job = match step.generator.start(&app).await {
+                Ok(started) => started.job,
+                Err(e) => {
+                    let error = format!("{}: {}", e.field, e.message);
+                    send(ScenarioEvent::Failed {
+                        scenario: id,
+                        step: step_nr,
+                        error: error.clone(),
+                    });
+                    return Err(format!("step {step_nr}: {error}"));
+                }
+            };
+            started.push(job.id);
+            send(ScenarioEvent::Step {
+                scenario: id,
+                step: step_nr,
+                job: job.clone(),
+            });
+            if step.wait {
+                let ended = tokio::select! {
+                    ended = app.jobs.wait(job.id) => ended,
+                    _ = token.cancelled() => break,
+                };
+                if let Some(job) = ended {
+                    send(ScenarioEvent::StepEnded {
+                        scenario: id,
+                        step: step_nr,
+                        job,
+                    });
+                }
+            }
+        }
+        if token.is_cancelled() {
+            // stop everything this scenario started as well
+            for job in started {
+                app.jobs.cancel(job).await;
+            }
+            send(ScenarioEvent::Cancelled { scenario: id });
+        } else {
+            send(ScenarioEvent::Finished { scenario: id });
+        }
+        Ok(())
+    }
+}
+
+/// Starts a scenario from a JSON body, or TOML with `Content-Type: application/toml`.
+pub async fn run(State(app): State<Arc<AppState>>, headers: HeaderMap, body: String) -> Response {
+    let is_toml = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("toml"));
+    let scenario = match Scenario::parse(&body, is_toml) {
+        Ok(scenario) => scenario,
+        Err(e) => return ValidationError::new("scenario", e).into_response(),
+    };
+    match ScenarioRunner::run(&app, scenario).await {
+        Ok(job) => (StatusCode::ACCEPTED, Json(job)).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    State(app): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let events = app.scenarios.subscribe();
+    let shutdown = app.shutdown.clone();
+    ws.on_upgrade(|socket| handle_socket(socket, events, shutdown))
+}
+
+async fn handle_socket(
+    mut socket: WebSocket,
+    mut events: broadcast::Receiver<ScenarioEvent>,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = shutdown.cancelled() => {
+                let _ = socket.send(shutdown::close_message()).await;
+                break;
+            }
+        };
+        match event {
+            Ok(event) => {
+                let text = serde_json::to_string(&event).unwrap_or_default();
+                if socket
+                    .send(axum::extract::ws::Message::Text(text))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("scenario socket lagged behind by {n} events");
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../scenarios/starve_workers.toml");
+
+    #[test]
+    fn toml_and_json_scenarios_parse() {
+        let scenario = Scenario::parse(EXAMPLE, true).unwrap();
+        assert_eq!(scenario.name, "starve the workers");
+        let kinds: Vec<&str> = scenario.steps.iter().map(|s| s.generator.kind()).collect();
+        assert_eq!(kinds, ["sleeper", "blockers", "cpuloadgen"]);
+        assert_eq!(scenario.steps[1].delay, 5);
+        assert!(scenario.steps[1].wait);
+        assert!(!scenario.steps[0].wait);
+
+        let json = r#"{"name": "short", "steps": [{"generator": "channel", "tasks": 2, "time": 1, "repeat": 3}]}"#;
+        let scenario = Scenario::parse(json, false).unwrap();
+        assert_eq!(scenario.steps[0].generator.kind(), "channel");
+    }
+
+    #[test]
+    fn malformed_scenarios_are_rejected() {
+        // TOML handed in as JSON
+        assert!(Scenario::parse(EXAMPLE, false).is_err());
+        assert!(Scenario::parse("name = \"unclosed", true).is_err());
+        let unknown = "name = \"x\"\n[[steps]]\ngenerator = \"forkbomb\"";
+        assert!(Scenario::parse(unknown, true)
+            .err()
+            .unwrap()
+            .contains("forkbomb"));
+        let incomplete = "name = \"x\"\n[[steps]]\ngenerator = \"sleeper\"\ntasks = 1";
+        assert!(Scenario::parse(incomplete, true)
+            .err()
+            .unwrap()
+            .contains("time"));
+        // typos would otherwise run the step with the wrong timing
+        let typo =
+            "name = \"x\"\n[[steps]]\ndealy = 5\ngenerator = \"sleeper\"\ntasks = 1\ntime = 1";
+        assert_eq!(
+            Scenario::parse(typo, true).err().unwrap(),
+            "step 1: unknown field 'dealy' for sleeper"
+        );
+        let typo = r#"{"name": "x", "steps": [{"generator": "channel", "tasks": 1, "time": 1, "repeat": 1, "wiat": true}]}"#;
+        assert!(Scenario::parse(typo, false)
+            .err()
+            .unwrap()
+            .contains("unknown field 'wiat'"));
+        assert!(
+            Scenario::parse("name = \"x\"\nsteps = []\nloop = true", true)
+                .err()
+                .unwrap()
+                .contains("loop")
+        );
+        let error = Scenario::load(Path::new("scenarios/missing.toml"))
+            .err()
+            .unwrap();
+        assert!(error.starts_with("cannot read scenario"), "{error}");
+    }
+
+    #[test]
+    fn every_step_is_validated_up_front() {
+        let scenario = Scenario::parse(EXAMPLE, true).unwrap();
+        let mut config = Config::default();
+        // blockers and cpu load are opt-in
+        let e = scenario.validate(&config).unwrap_err();
+        assert_eq!(e.field, "generator");
+        assert_eq!(e.message, "step 2: 'blockers' is not enabled");
+        config.features.blockers = true;
+        config.features.cpu_load = true;
+        assert!(scenario.validate(&config).is_ok());
+        config.limits.sleeper.tasks.max = 100;
+        let e = scenario.validate(&config).unwrap_err();
+        assert_eq!(e.field, "tasks");
+        assert!(e.message.starts_with("step 1: 10000 is out of range"));
+    }
+}
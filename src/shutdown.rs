@@ -0,0 +1,107 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::extract::ws::{close_code, CloseFrame, Message};
+use tokio_util::sync::CancellationToken;
+
+use crate::AppState;
+
+/// Cancels `shutdown` on SIGINT or SIGTERM.
+pub async fn signal(shutdown: CancellationToken) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("cannot listen for ctrl-c: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                log::error!("cannot listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => log::info!("received SIGINT, shutting down"),
+        _ = terminate => log::info!("received SIGTERM, shutting down"),
+        _ = shutdown.cancelled() => {}
+    }
+    shutdown.cancel();
+}
+
+/// Close frame sent to websocket clients when the server goes away.
+pub fn close_message() -> Message {
+    Message::Close(Some(CloseFrame {
+        code: close_code::AWAY,
+        reason: "server shutting down".into(),
+    }))
+}
+
+/// Stops all background work after the server stopped accepting connections
+/// and waits for running jobs, at most until `deadline`.
+pub async fn drain(app: Arc<AppState>, deadline: Duration) {
+    app.jobs.cancel_all().await;
+    app.stats.shutdown();
+    if let Some(rooms) = &app.soccer_rooms {
+        rooms.shutdown().await;
+    }
+    let jobs = Arc::clone(&app.jobs);
+    if tokio::time::timeout(deadline, jobs.wait_all())
+        .await
+        .is_err()
+    {
+        log::warn!("jobs still running after {deadline:?}, exiting anyway");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{config::Config, jobs::JobStatus};
+
+    #[tokio::test]
+    async fn drain_cancels_jobs_and_gives_up_after_the_deadline() {
+        let app = AppState::for_tests(Config::default());
+        let polite = app
+            .jobs
+            .start("sleeper", Value::Null, |_, token| async move {
+                token.cancelled().await;
+                Ok(())
+            })
+            .await;
+        let stubborn = app
+            .jobs
+            .start("blockers", Value::Null, |_, _| async move {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })
+            .await;
+
+        let started = Instant::now();
+        drain(Arc::clone(&app), Duration::from_millis(300)).await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let polite = app.jobs.get(polite.id).await.unwrap();
+        assert!(matches!(polite.status, JobStatus::Cancelled));
+        let stubborn = app.jobs.get(stubborn.id).await.unwrap();
+        assert!(matches!(stubborn.status, JobStatus::Running));
+    }
+
+    #[test]
+    fn clients_are_told_the_server_goes_away() {
+        let Message::Close(Some(frame)) = close_message() else {
+            panic!("not a close frame");
+        };
+        assert_eq!(frame.code, close_code::AWAY);
+    }
+}
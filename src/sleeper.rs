@@ -1,18 +1,38 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use axum::Form;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
 
-#[derive(Deserialize)]
+use crate::{
+    config::Config,
+    generator::{LoadGenerator, ValidationError},
+    jobs,
+    limits::Limits,
+};
+
+#[derive(Deserialize, Serialize)]
 pub struct SleeperForm {
     tasks: u64,
     time: u64,
 }
 
-pub async fn sleeper(Form(f): Form<SleeperForm>) {
-    let time = Duration::from_secs(f.time);
-    let s = Sleeper::new(f.tasks, time);
-    s.spawn().await;
+impl LoadGenerator for SleeperForm {
+    const KIND: &'static str = "sleeper";
+
+    fn validate(&self, limits: &Limits) -> Result<(), ValidationError> {
+        limits.sleeper.tasks.check("tasks", self.tasks)?;
+        limits.sleeper.time.check("time", self.time)
+    }
+
+    fn estimated_duration(&self, _config: &Config) -> Option<Duration> {
+        Some(Duration::from_secs(self.time))
+    }
+
+    async fn run(self, _config: Arc<Config>, token: CancellationToken) -> Result<(), String> {
+        Sleeper::new(self.tasks, Duration::from_secs(self.time))
+            .run(token)
+            .await
+    }
 }
 
 // A simple struct that spawns a number of tokio tasks, which are just sleeping
@@ -26,12 +46,18 @@ impl Sleeper {
         Self { tasks, time }
     }
 
-    pub async fn spawn(&self) {
+    pub async fn run(self, token: CancellationToken) -> Result<(), String> {
+        let mut handles = Vec::with_capacity(self.tasks as usize);
         for _ in 0..self.tasks {
             let t = self.time;
-            tokio::spawn(async move {
-                tokio::time::sleep(t).await;
-            });
+            let token = token.clone();
+            handles.push(tokio::spawn(async move {
+                tokio::select! {
+                    _ = tokio::time::sleep(t) => {}
+                    _ = token.cancelled() => {}
+                }
+            }));
         }
+        jobs::join_all(handles).await
     }
 }
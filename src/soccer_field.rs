@@ -1,188 +1,600 @@
 use std::{
-    collections::HashMap,
-    sync::Arc,
-    thread::JoinHandle,
+    collections::{BTreeSet, HashMap},
+    ops::{Add, Mul, Sub},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
 use axum::{
     extract::{
-        ws::{Message, WebSocket},
-        State, WebSocketUpgrade,
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        Path, Query, State, WebSocketUpgrade,
     },
-    response::{Html, IntoResponse},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    Json,
 };
 use futures::{SinkExt, StreamExt};
-use minijinja::{context, path_loader};
-use rand::Rng;
-use serde::Serialize;
-use tokio::sync::{broadcast, mpsc, oneshot};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    time::MissedTickBehavior,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::Config, generator::ValidationError, shutdown, templates::TemplateError, AppState,
+};
+
+use bots::BotsInfo;
+pub use bots::{BotDifficulty, MAX_BOTS};
+use input::{ClientError, ErrorCode, RateLimiter, MAX_MESSAGE_SIZE};
+use protocol::{DeltaEncoder, Protocol};
+use replay::{Recorder, Replay};
+pub use rooms::Rooms;
+use rooms::DEFAULT_ROOM;
+pub use runtime::SoccerRuntime;
+use runtime::{Runner, Simulation};
+use telemetry::Telemetry;
+pub use telemetry::TelemetrySample;
 
-use crate::AppState;
+mod bots;
+mod input;
+mod protocol;
+mod replay;
+mod rooms;
+mod runtime;
+mod telemetry;
 
 const FIELD_BOUNDARY_Y: i16 = 800;
 const FIELD_BOUNDARY_X: i16 = 400;
+/// Field units per tick.
 const MAX_PLAYER_SPEED: f32 = 5.0;
+/// Share of the difference to the desired velocity a player makes up per tick.
 const PLAYER_ACCELERATION: f32 = 0.1;
+const MAX_BALL_SPEED: f32 = MAX_PLAYER_SPEED * 1.5;
+/// Ball velocity kept per tick.
+const BALL_FRICTION: f32 = 0.98;
+/// Share of the approach speed the ball keeps when it bounces off a player.
+const BALL_RESTITUTION: f32 = 0.5;
+const PLAYER_RADIUS: f32 = 8.0;
+const BALL_RADIUS: f32 = 8.0;
+/// Simulation ticks per second.
 const TICKRATE: u16 = 128;
+/// Position updates per second, also the highest rate a client can ask for.
+const BROADCAST_RATE: u16 = 60;
+const MAX_CATCH_UP_TICKS: u32 = 16;
+/// A client which does not take an update within this time is dropped.
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_REPLAY_SPEED: f32 = 0.1;
+const MAX_REPLAY_SPEED: f32 = 16.0;
+const GOAL_WIDTH: i16 = 50;
+const HALF_DURATION: Duration = Duration::from_secs(180);
+const HALF_TIME_BREAK: Duration = Duration::from_secs(10);
+const FULL_TIME_BREAK: Duration = Duration::from_secs(15);
+const MAX_NAME_LEN: usize = 16;
+/// Highest update rate of the low fidelity spectator stream.
+const LOW_FIDELITY_RATE: u16 = 10;
+/// Positions of the low fidelity stream are rounded to multiples of this.
+const LOW_FIDELITY_GRID: i16 = 8;
+
+/// Kickoff positions of the home team, which defends the goal at y = 0. The
+/// away team uses the same slots mirrored, players beyond the last slot start
+/// over at the first one.
+const FORMATION: [Coordinate; 6] = [
+    Coordinate(60, 200),
+    Coordinate(180, 110),
+    Coordinate(180, 290),
+    Coordinate(300, 200),
+    Coordinate(280, 60),
+    Coordinate(280, 340),
+];
 
 #[derive(Debug)]
 struct SoccerField {
     players: HashMap<u16, Player>,
     ball: Ball,
     id_inc: u16,
+    score: Score,
+    clock: MatchClock,
+    tick: u64,
+    /// Player ids of the bots.
+    bots: BTreeSet<u16>,
+    bot_difficulty: BotDifficulty,
+    /// Connected spectators, set by the simulation thread.
+    spectators: u16,
+}
+
+/// Goals per team, `home` defends the goal at y = 0, `away` the one at
+/// y = FIELD_BOUNDARY_Y.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+struct Score {
+    home: u16,
+    away: u16,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum MatchPhase {
+    FirstHalf,
+    HalfTime,
+    SecondHalf,
+    FullTime,
+}
+
+impl MatchPhase {
+    fn duration(&self) -> Duration {
+        match self {
+            MatchPhase::FirstHalf | MatchPhase::SecondHalf => HALF_DURATION,
+            MatchPhase::HalfTime => HALF_TIME_BREAK,
+            MatchPhase::FullTime => FULL_TIME_BREAK,
+        }
+    }
+
+    fn next(&self) -> Self {
+        match self {
+            MatchPhase::FirstHalf => MatchPhase::HalfTime,
+            MatchPhase::HalfTime => MatchPhase::SecondHalf,
+            MatchPhase::SecondHalf => MatchPhase::FullTime,
+            MatchPhase::FullTime => MatchPhase::FirstHalf,
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        matches!(self, MatchPhase::FirstHalf | MatchPhase::SecondHalf)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct MatchClock {
+    phase: MatchPhase,
+    elapsed: Duration,
+}
+
+impl MatchClock {
+    fn new() -> Self {
+        Self {
+            phase: MatchPhase::FirstHalf,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Moves the clock forward, returns the new phase if one started.
+    fn advance(&mut self, dt: Duration) -> Option<MatchPhase> {
+        self.elapsed += dt;
+        if self.elapsed < self.phase.duration() {
+            return None;
+        }
+        self.phase = self.phase.next();
+        self.elapsed = Duration::ZERO;
+        Some(self.phase)
+    }
+}
+
+/// Match clock as sent to the clients.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct ClockState {
+    phase: MatchPhase,
+    elapsed_secs: u64,
+    duration_secs: u64,
+}
+
+impl From<&MatchClock> for ClockState {
+    fn from(clock: &MatchClock) -> Self {
+        Self {
+            phase: clock.phase,
+            elapsed_secs: clock.elapsed.as_secs(),
+            duration_secs: clock.phase.duration().as_secs(),
+        }
+    }
 }
 
 #[derive(Debug)]
 struct Ball {
-    position: Coordinate,
-    speed: Speed,
+    position: Vec2,
+    velocity: Vec2,
 }
 
 #[derive(Debug)]
 struct Player {
-    target: Coordinate,
-    position: Coordinate,
-    speed: Speed,
+    team: Team,
+    name: Option<String>,
+    spawn: Vec2,
+    target: Vec2,
+    position: Vec2,
+    velocity: Vec2,
 }
 
-#[derive(Copy, Clone, Debug, Serialize)]
+/// Rounded position as sent to the clients.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
 struct Coordinate(i16, i16);
 //                 y    x
 
-#[derive(Copy, Clone, Debug)]
-struct Speed(f32, f32);
-//            y    x
+/// Position or velocity in field units, velocities are per tick.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Vec2(f32, f32);
+//          y    x
+
+impl Vec2 {
+    fn dot(self, other: Self) -> f32 {
+        self.0 * other.0 + self.1 * other.1
+    }
+    fn length(self) -> f32 {
+        self.dot(self).sqrt()
+    }
+    /// Same direction, at most `max` long.
+    fn clamp_length(self, max: f32) -> Self {
+        let length = self.length();
+        if length > max {
+            self * (max / length)
+        } else {
+            self
+        }
+    }
+    /// Unit vector in the same direction, `fallback` for the zero vector.
+    fn normalize_or(self, fallback: Self) -> Self {
+        let length = self.length();
+        if length > f32::EPSILON {
+            self * (1.0 / length)
+        } else {
+            fallback
+        }
+    }
+    /// Keeps a circle of `radius` inside the field.
+    fn clamp_to_field(self, radius: f32) -> Self {
+        Vec2(
+            self.0.clamp(radius, FIELD_BOUNDARY_Y as f32 - radius),
+            self.1.clamp(radius, FIELD_BOUNDARY_X as f32 - radius),
+        )
+    }
+}
+
+impl Add for Vec2 {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Vec2(self.0 + other.0, self.1 + other.1)
+    }
+}
 
-fn update_pos_with_speed(pos: i16, speed: f32) -> i16 {
-    (pos as f32 + speed).round() as i16
+impl Sub for Vec2 {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Vec2(self.0 - other.0, self.1 - other.1)
+    }
 }
 
-impl Coordinate {
-    fn apply_speed_with_boundaries(&mut self, speed: Speed) {
-        self.0 = update_pos_with_speed(self.0, speed.0).clamp(8, FIELD_BOUNDARY_Y - 8);
-        self.1 = update_pos_with_speed(self.1, speed.1).clamp(8, FIELD_BOUNDARY_X - 8);
+impl Mul<f32> for Vec2 {
+    type Output = Self;
+    fn mul(self, factor: f32) -> Self {
+        Vec2(self.0 * factor, self.1 * factor)
     }
-    fn distance_angle(&self, other: &Self) -> (f32, f32) {
-        let dy: f32 = (self.0 - other.0) as f32;
-        let dx: f32 = (self.1 - other.1) as f32;
-        (f32::sqrt(dx * dx + dy * dy), f32::atan2(dy, dx))
+}
+
+impl From<Coordinate> for Vec2 {
+    fn from(Coordinate(y, x): Coordinate) -> Self {
+        Vec2(y as f32, x as f32)
     }
 }
 
-impl Speed {
-    fn reduce(&mut self) {
-        self.0 *= 0.9;
-        self.1 *= 0.9;
-        if self.0 == 0.0 {
-            self.1 = 0.0;
-        }
-        if self.1 == 0.0 {
-            self.0 = 0.0;
-        }
+impl From<Vec2> for Coordinate {
+    fn from(Vec2(y, x): Vec2) -> Self {
+        Coordinate(y.round() as i16, x.round() as i16)
     }
 }
 
 impl Player {
     fn update_target(&mut self, y: i16, x: i16) {
-        self.target.0 = y;
-        self.target.1 = x;
+        self.target = Vec2::from(Coordinate(y, x)).clamp_to_field(PLAYER_RADIUS);
     }
-    fn update(&mut self, ball: &mut Ball) {
-        let (distance, angle) = self.target.distance_angle(&self.position);
-        let distance_speed = if distance * PLAYER_ACCELERATION > MAX_PLAYER_SPEED {
-            distance * PLAYER_ACCELERATION
-        } else {
-            MAX_PLAYER_SPEED
-        };
-        if distance > 2.5 {
-            self.speed.0 = distance_speed * angle.sin();
-            self.speed.1 = distance_speed * angle.cos();
-            self.position.apply_speed_with_boundaries(self.speed);
+    /// Steers towards the target, never faster than `MAX_PLAYER_SPEED`.
+    fn update(&mut self) {
+        // close to the target the desired velocity shrinks, so players stop on it
+        let desired = (self.target - self.position).clamp_length(MAX_PLAYER_SPEED);
+        self.velocity = self.velocity + (desired - self.velocity) * PLAYER_ACCELERATION;
+        self.position = (self.position + self.velocity).clamp_to_field(PLAYER_RADIUS);
+    }
+}
+
+/// Separates two overlapping players and cancels the part of their velocities
+/// which moves them into each other.
+fn collide_players(a: &mut Player, b: &mut Player) {
+    let delta = b.position - a.position;
+    let overlap = 2.0 * PLAYER_RADIUS - delta.length();
+    if overlap <= 0.0 {
+        return;
+    }
+    let normal = delta.normalize_or(Vec2(0.0, 1.0));
+    a.position = (a.position - normal * (overlap / 2.0)).clamp_to_field(PLAYER_RADIUS);
+    b.position = (b.position + normal * (overlap / 2.0)).clamp_to_field(PLAYER_RADIUS);
+    let approach = (a.velocity - b.velocity).dot(normal);
+    if approach > 0.0 {
+        // equal masses, both end up with the same speed along the normal
+        a.velocity = a.velocity - normal * (approach / 2.0);
+        b.velocity = b.velocity + normal * (approach / 2.0);
+    }
+}
+
+/// Pushes the ball out of the player and bounces it off the moving player.
+fn collide_ball(player: &Player, ball: &mut Ball) {
+    let delta = ball.position - player.position;
+    let min_distance = PLAYER_RADIUS + BALL_RADIUS;
+    if delta.length() >= min_distance {
+        return;
+    }
+    let normal = delta.normalize_or(player.velocity.normalize_or(Vec2(1.0, 0.0)));
+    ball.position = (player.position + normal * min_distance).clamp_to_field(BALL_RADIUS);
+    let approach = (ball.velocity - player.velocity).dot(normal);
+    if approach < 0.0 {
+        // the player is much heavier than the ball and keeps its velocity
+        ball.velocity = ball.velocity - normal * ((1.0 + BALL_RESTITUTION) * approach);
+        ball.velocity = ball.velocity.clamp_length(MAX_BALL_SPEED);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Team {
+    Home,
+    Away,
+}
+
+impl Team {
+    fn color(&self) -> &'static str {
+        match self {
+            Team::Home => "#1565C0",
+            Team::Away => "#C62828",
         }
-        // check collision
-        let (ball_distance, ball_angle) = self.position.distance_angle(&ball.position);
-        if ball_distance < 18.0 {
-            ball.speed.0 = distance_speed * 1.5 * ball_angle.cos();
-            ball.speed.1 = distance_speed * 1.5 * ball_angle.sin();
-            ball.position.apply_speed_with_boundaries(ball.speed);
+    }
+
+    /// Kickoff position of the `slot`th player of the team.
+    fn spawn(&self, slot: usize) -> Coordinate {
+        let Coordinate(y, x) = FORMATION[slot % FORMATION.len()];
+        match self {
+            Team::Home => Coordinate(y, x),
+            Team::Away => Coordinate(FIELD_BOUNDARY_Y - y, FIELD_BOUNDARY_X - x),
+        }
+    }
+}
+
+/// Team colors as sent to the clients.
+#[derive(Clone, Debug, Serialize)]
+struct TeamColors {
+    home: &'static str,
+    away: &'static str,
+}
+
+impl Default for TeamColors {
+    fn default() -> Self {
+        Self {
+            home: Team::Home.color(),
+            away: Team::Away.color(),
         }
     }
 }
 
 impl Ball {
+    fn kickoff() -> Self {
+        Ball {
+            position: Vec2(FIELD_BOUNDARY_Y as f32 / 2.0, FIELD_BOUNDARY_X as f32 / 2.0),
+            velocity: Vec2::default(),
+        }
+    }
+
     fn update(&mut self) {
-        self.update_speed();
-        self.update_position();
+        self.velocity = self.velocity * BALL_FRICTION;
+        if self.velocity.length() < 0.01 {
+            self.velocity = Vec2::default();
+        }
+        self.position = self.position + self.velocity;
         self.bounce();
     }
-    fn update_position(&mut self) {
-        self.position.apply_speed_with_boundaries(self.speed);
+    fn bounce(&mut self) {
+        let Vec2(y, x) = self.position;
+        let (max_y, max_x) = (
+            FIELD_BOUNDARY_Y as f32 - BALL_RADIUS,
+            FIELD_BOUNDARY_X as f32 - BALL_RADIUS,
+        );
+        if y <= BALL_RADIUS {
+            self.velocity.0 = self.velocity.0.abs();
+        } else if y >= max_y {
+            self.velocity.0 = -self.velocity.0.abs();
+        }
+        if x <= BALL_RADIUS {
+            self.velocity.1 = self.velocity.1.abs();
+        } else if x >= max_x {
+            self.velocity.1 = -self.velocity.1.abs();
+        }
+        self.position = self.position.clamp_to_field(BALL_RADIUS);
     }
-    fn update_speed(&mut self) {
-        self.speed.reduce();
+}
+
+/// Turns the real time which passed into a number of fixed simulation ticks,
+/// the remainder is carried over to the next call.
+#[derive(Debug)]
+struct FixedTimestep {
+    tick: Duration,
+    accumulated: Duration,
+}
+
+impl FixedTimestep {
+    fn new(tick: Duration) -> Self {
+        Self {
+            tick,
+            accumulated: Duration::ZERO,
+        }
     }
-    fn bounce(&mut self) {
-        // checking limits
-        match self.position {
-            Coordinate(y, _) if { y >= FIELD_BOUNDARY_Y - 8 } => {
-                self.speed.0 *= -1.0;
-            }
-            Coordinate(y, _) if { y <= 8 } => {
-                self.speed.0 *= -1.0;
-            }
-            Coordinate(_, x) if { x >= FIELD_BOUNDARY_X - 8 } => {
-                self.speed.1 *= -1.0;
-            }
-            Coordinate(_, x) if { x <= 8 } => {
-                self.speed.1 *= -1.0;
-            }
-            Coordinate(_, _) => {}
+
+    /// Ticks to simulate for `elapsed`, at most `MAX_CATCH_UP_TICKS`. Time
+    /// beyond that is dropped, the match slows down instead of stalling.
+    fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulated += elapsed;
+        let mut ticks = 0;
+        while self.accumulated >= self.tick {
+            self.accumulated -= self.tick;
+            ticks += 1;
+        }
+        if ticks > MAX_CATCH_UP_TICKS {
+            log::warn!(
+                "soccer field is {} ticks behind, skipping them",
+                ticks - MAX_CATCH_UP_TICKS
+            );
+            ticks = MAX_CATCH_UP_TICKS;
         }
+        ticks
+    }
+
+    /// Time until the next tick is due.
+    fn until_next(&self) -> Duration {
+        self.tick.saturating_sub(self.accumulated)
     }
 }
 
 impl SoccerField {
-    fn update(&mut self) {
-        for (_, player) in self.players.iter_mut() {
-            player.update(&mut self.ball);
+    /// Advances the match by one tick of `tick_duration()`.
+    fn step(&mut self) {
+        self.tick += 1;
+        self.steer_bots();
+        if let Some(phase) = self.clock.advance(tick_duration()) {
+            log::info!("match phase {:?}, score {:?}", phase, self.score);
+            match phase {
+                MatchPhase::FirstHalf => {
+                    self.score = Score::default();
+                    self.kickoff();
+                }
+                MatchPhase::SecondHalf => self.kickoff(),
+                MatchPhase::HalfTime | MatchPhase::FullTime => {}
+            }
+        }
+        let mut players: Vec<&mut Player> = self.players.values_mut().collect();
+        for player in players.iter_mut() {
+            player.update();
+        }
+        for i in 1..players.len() {
+            let (left, right) = players.split_at_mut(i);
+            let a = &mut left[i - 1];
+            for b in right.iter_mut() {
+                collide_players(a, b);
+            }
+        }
+        if !self.clock.phase.is_running() {
+            // the ball rests on the center spot during breaks
+            self.ball = Ball::kickoff();
+            return;
         }
         self.ball.update();
+        for player in players.iter() {
+            collide_ball(player, &mut self.ball);
+        }
+        if let Some(scorer) = self.goal() {
+            match scorer {
+                Team::Home => self.score.home += 1,
+                Team::Away => self.score.away += 1,
+            }
+            log::info!("goal for {:?}, score {:?}", scorer, self.score);
+            self.kickoff();
+        }
+    }
+
+    /// The side which scored, if the ball is in one of the goals.
+    fn goal(&self) -> Option<Team> {
+        let Vec2(y, x) = self.ball.position;
+        let goal_mouth = (FIELD_BOUNDARY_X - GOAL_WIDTH) as f32 / 2.0
+            ..=(FIELD_BOUNDARY_X + GOAL_WIDTH) as f32 / 2.0;
+        if !goal_mouth.contains(&x) {
+            return None;
+        }
+        if y <= BALL_RADIUS {
+            Some(Team::Away)
+        } else if y >= FIELD_BOUNDARY_Y as f32 - BALL_RADIUS {
+            Some(Team::Home)
+        } else {
+            None
+        }
+    }
+
+    /// Ball back to the center spot, players back to their spawn.
+    fn kickoff(&mut self) {
+        self.ball = Ball::kickoff();
+        for player in self.players.values_mut() {
+            player.position = player.spawn;
+            player.target = player.spawn;
+            player.velocity = Vec2::default();
+        }
     }
 
     fn new() -> Self {
         Self {
             players: HashMap::new(),
-            ball: Ball {
-                position: Coordinate(400, 200),
-                speed: Speed(0.0, 0.0),
-            },
+            ball: Ball::kickoff(),
             id_inc: 0,
+            score: Score::default(),
+            clock: MatchClock::new(),
+            tick: 0,
+            bots: BTreeSet::new(),
+            bot_difficulty: BotDifficulty::default(),
+            spectators: 0,
         }
     }
-    fn player_join(&mut self) -> u16 {
-        let player_id = self.id_inc;
-        self.id_inc += 1;
+    /// Adds a player to the team with fewer players, home on a tie.
+    fn player_join(&mut self, name: Option<String>) -> (u16, Team) {
+        // ids wrap around after u16::MAX joins, which bots reach by being
+        // replaced over and over, so skip the ones still on the field
+        let mut player_id = self.id_inc;
+        while self.players.contains_key(&player_id) {
+            player_id = player_id.wrapping_add(1);
+        }
+        self.id_inc = player_id.wrapping_add(1);
+        let home = self.team_size(Team::Home);
+        let away = self.team_size(Team::Away);
+        let (team, slot) = if away < home {
+            (Team::Away, away)
+        } else {
+            (Team::Home, home)
+        };
+        let spawn = team.spawn(slot).into();
         self.players.insert(
             player_id,
             Player {
-                target: Coordinate(0, 0),
-                position: Coordinate(0, 0),
-                speed: Speed(0.0, 0.0),
+                team,
+                name,
+                spawn,
+                target: spawn,
+                position: spawn,
+                velocity: Vec2::default(),
             },
         );
-        player_id
+        (player_id, team)
+    }
+    fn team_size(&self, team: Team) -> usize {
+        self.players.values().filter(|p| p.team == team).count()
     }
     fn player_leave(&mut self, id: u16) {
         self.players.remove(&id);
+        self.bots.remove(&id);
     }
     fn positions(&self) -> PositionsList {
         PositionsList {
-            ball: self.ball.position,
+            tick: self.tick,
+            score: self.score,
+            clock: (&self.clock).into(),
+            colors: TeamColors::default(),
+            ball: self.ball.position.into(),
+            spectators: self.spectators,
             players: self
                 .players
                 .iter()
-                .map(|(id, p)| (*id, p.position))
+                .map(|(id, p)| {
+                    let state = PlayerState {
+                        position: p.position.into(),
+                        team: p.team,
+                        name: p.name.clone(),
+                    };
+                    (*id, state)
+                })
                 .collect(),
         }
     }
@@ -190,153 +602,577 @@ impl SoccerField {
 
 #[derive(Clone, Debug, Serialize)]
 pub struct PositionsList {
+    tick: u64,
+    score: Score,
+    clock: ClockState,
+    colors: TeamColors,
     ball: Coordinate,
-    players: HashMap<u16, Coordinate>,
+    spectators: u16,
+    players: HashMap<u16, PlayerState>,
+}
+
+impl PositionsList {
+    /// Copy with all positions rounded to `LOW_FIDELITY_GRID`, so small moves
+    /// are not sent at all.
+    fn coarse(&self) -> PositionsList {
+        let snap = |Coordinate(y, x): Coordinate| {
+            let round =
+                |v: i16| (v as f32 / LOW_FIDELITY_GRID as f32).round() as i16 * LOW_FIDELITY_GRID;
+            Coordinate(round(y), round(x))
+        };
+        let mut coarse = self.clone();
+        coarse.ball = snap(coarse.ball);
+        for player in coarse.players.values_mut() {
+            player.position = snap(player.position);
+        }
+        coarse
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct PlayerState {
+    position: Coordinate,
+    team: Team,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
 }
 
-#[derive(Clone, Debug)]
+/// Messages sent to the websocket clients of the field.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    /// First message after joining, `id` is the player of this connection.
+    Welcome {
+        id: u16,
+        team: Team,
+        colors: TeamColors,
+        tick_rate: u16,
+        /// Position updates per second this connection gets.
+        rate: u16,
+        protocol: Protocol,
+        runtime: SoccerRuntime,
+    },
+    /// First message to a spectator, which has no player.
+    Spectate {
+        colors: TeamColors,
+        tick_rate: u16,
+        rate: u16,
+        protocol: Protocol,
+        fidelity: Fidelity,
+        runtime: SoccerRuntime,
+    },
+    Positions(&'a PositionsList),
+    /// Answer to a message the server could not use.
+    Error(&'a ClientError),
+    /// First message of a replay, followed by binary frames.
+    Replay {
+        name: &'a str,
+        colors: TeamColors,
+        tick_rate: u16,
+        speed: f32,
+    },
+}
+
+impl ServerMessage<'_> {
+    fn to_message(&self) -> Message {
+        Message::Text(serde_json::to_string(self).unwrap())
+    }
+}
+
+#[derive(Debug)]
 enum PlayerUpdates {
     Position(u16, i16, i16),
-    Join(u16),
+    /// Display name, answered with the id and team of the new player.
+    Join(Option<String>, oneshot::Sender<(u16, Team)>),
     Leave(u16),
-}
-
-#[derive(Clone, Debug)]
-enum FieldUpdates {
-    Positions(PositionsList),
-    Joined(u16, u16),
+    /// Number of bots and their difficulty, answered with the bots on the field.
+    Bots(u16, Option<BotDifficulty>, oneshot::Sender<BotsInfo>),
 }
 
 pub struct SoccerFieldThread {
-    _handle: JoinHandle<()>,
-    _exit: oneshot::Sender<bool>,
-    field_updates: broadcast::Sender<FieldUpdates>,
+    runtime: SoccerRuntime,
+    runner: Mutex<Option<Runner>>,
+    exit: Mutex<Option<oneshot::Sender<bool>>>,
+    /// Latest positions, slow clients skip states instead of queueing them.
+    positions: watch::Receiver<Arc<PositionsList>>,
     player_updates: mpsc::Sender<PlayerUpdates>,
+    /// Players on the field, bots included.
+    players: Arc<AtomicUsize>,
+    /// Connected websocket clients, spectators included.
+    clients: AtomicUsize,
+    spectators: Arc<AtomicUsize>,
+    telemetry: Arc<Telemetry>,
 }
 
 impl SoccerFieldThread {
-    pub fn spawn() -> Self {
-        let (fu_tx, _) = broadcast::channel(2048);
+    /// Starts the simulation of `room` with `bots` on `runtime`.
+    pub fn spawn(room: &str, bots: u16, runtime: SoccerRuntime, config: &Config) -> Self {
+        let mut field = SoccerField::new();
+        field.set_bots(bots, Some(config.soccer_bot_difficulty));
+        let (positions_tx, positions) = watch::channel(Arc::new(field.positions()));
         let (player_updates, pu_rx) = mpsc::channel(2048);
         let (exit, exit_rx) = oneshot::channel();
+        let players = Arc::new(AtomicUsize::new(field.players.len()));
+        let recorder = config
+            .soccer_replay_dir
+            .as_deref()
+            .map(|dir| Recorder::new(dir, room));
 
-        let field_updates = fu_tx.clone();
+        let spectators = Arc::new(AtomicUsize::new(0));
+        let telemetry = Arc::new(Telemetry::default());
 
-        let handle = std::thread::spawn(move || Self::thread(fu_tx.clone(), pu_rx, exit_rx));
+        let simulation = Simulation {
+            field,
+            recorder,
+            positions: positions_tx,
+            player_updates: pu_rx,
+            players: Arc::clone(&players),
+            spectators: Arc::clone(&spectators),
+            telemetry: Arc::clone(&telemetry),
+        };
+        let runner = Runner::spawn(runtime, room, simulation, exit_rx);
 
         SoccerFieldThread {
-            _handle: handle,
-            _exit: exit,
-            field_updates,
+            runtime,
+            runner: Mutex::new(Some(runner)),
+            exit: Mutex::new(Some(exit)),
+            positions,
             player_updates,
+            players,
+            clients: AtomicUsize::new(0),
+            spectators,
+            telemetry,
         }
     }
 
-    fn thread(
-        field_updates: broadcast::Sender<FieldUpdates>,
-        mut player_updates: mpsc::Receiver<PlayerUpdates>,
-        mut exit: oneshot::Receiver<bool>,
-    ) {
-        // init soccerfield
-        let mut field = SoccerField::new();
-        let mut last_debug_print = Instant::now();
-        let tick_duration = std::time::Duration::from_millis(1000 / TICKRATE as u64);
-        let mut last_tick = Instant::now();
-        let internal_duration = std::time::Duration::from_millis(3);
-        loop {
-            std::thread::sleep(internal_duration);
-            if exit.try_recv().is_ok() {
+    pub fn players(&self) -> usize {
+        self.players.load(Ordering::Relaxed)
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.load(Ordering::Relaxed)
+    }
+
+    pub fn spectators(&self) -> usize {
+        self.spectators.load(Ordering::Relaxed)
+    }
+
+    pub fn runtime(&self) -> SoccerRuntime {
+        self.runtime
+    }
+
+    /// Signals the simulation to exit and waits for it.
+    pub async fn shutdown(&self) {
+        if let Some(exit) = self.exit.lock().ok().and_then(|mut e| e.take()) {
+            let _ = exit.send(true);
+        }
+        let runner = self.runner.lock().ok().and_then(|mut r| r.take());
+        if let Some(runner) = runner {
+            runner.join().await;
+            log::info!("soccer field simulation stopped");
+        }
+    }
+}
+
+fn tick_duration() -> Duration {
+    Duration::from_secs(1) / TICKRATE as u32
+}
+
+pub async fn get_field(State(app): State<Arc<AppState>>) -> Result<Html<String>, TemplateError> {
+    app.templates.render("field.html", context! {})
+}
+
+#[derive(Deserialize)]
+pub struct BotsForm {
+    /// Defaults to the room of `/soccer_field/ws`.
+    room: Option<String>,
+    count: u16,
+    /// Keeps the current difficulty if not set.
+    difficulty: Option<BotDifficulty>,
+}
+
+/// Sets the number of bots on the field.
+pub async fn set_bots(State(app): State<Arc<AppState>>, Json(form): Json<BotsForm>) -> Response {
+    let Some(rooms) = app.soccer_rooms.as_ref() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let room = form.room.as_deref().unwrap_or(DEFAULT_ROOM);
+    let Some(thread) = rooms.get(room) else {
+        return ValidationError::new("room", format!("room '{room}' is not open")).into_response();
+    };
+    if form.count > MAX_BOTS {
+        return ValidationError::new("count", format!("at most {MAX_BOTS} bots")).into_response();
+    }
+    let (reply, done) = oneshot::channel();
+    let update = PlayerUpdates::Bots(form.count, form.difficulty, reply);
+    if thread.player_updates.send(update).await.is_err() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    match done.await {
+        Ok(bots) => Json(bots).into_response(),
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JoinParams {
+    /// Display name shown next to the player.
+    name: Option<String>,
+    /// Position updates per second, at most `BROADCAST_RATE`.
+    rate: Option<u16>,
+    #[serde(default)]
+    protocol: Protocol,
+    /// Watch the field without joining as a player.
+    #[serde(default)]
+    spectate: bool,
+    #[serde(default)]
+    fidelity: Fidelity,
+    /// Where the room runs, only used when this join opens it.
+    runtime: Option<SoccerRuntime>,
+}
+
+/// Detail of the position updates, chosen with `?fidelity=` on connect.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fidelity {
+    #[default]
+    Full,
+    /// At most `LOW_FIDELITY_RATE` updates with positions rounded to
+    /// `LOW_FIDELITY_GRID`, only for spectators.
+    Low,
+}
+
+/// Settings of one websocket client, taken from `JoinParams`.
+struct ClientOptions {
+    name: Option<String>,
+    rate: u16,
+    protocol: Protocol,
+    spectate: bool,
+    fidelity: Fidelity,
+}
+
+/// Trims the name to `MAX_NAME_LEN` printable characters, `None` if nothing is left.
+fn display_name(name: Option<String>) -> Option<String> {
+    let name: String = name?
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LEN)
+        .collect();
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Open rooms with their players and connected clients.
+pub async fn rooms(State(app): State<Arc<AppState>>) -> Response {
+    match app.soccer_rooms.as_ref() {
+        Some(rooms) => Json(rooms.list()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Recordings in `soccer_replay_dir`, newest first.
+pub async fn replays(State(app): State<Arc<AppState>>) -> Response {
+    let Some(dir) = app.config.soccer_replay_dir.clone() else {
+        return Json(Vec::<()>::new()).into_response();
+    };
+    match tokio::task::spawn_blocking(move || replay::list(&dir)).await {
+        Ok(Ok(replays)) => Json(replays).into_response(),
+        Ok(Err(e)) => {
+            log::error!("cannot list replays: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PlaybackParams {
+    /// 1.0 plays at the original speed.
+    speed: Option<f32>,
+}
+
+/// Plays a recording back as `binary` protocol frames.
+pub async fn replay_websocket_handler(
+    State(app): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(params): Query<PlaybackParams>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let path = app
+        .config
+        .soccer_replay_dir
+        .as_deref()
+        .and_then(|dir| replay::path(dir, &name));
+    let Some(path) = path else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let replay = match tokio::fs::read(&path).await {
+        Ok(data) => match Replay::parse(data) {
+            Ok(replay) => replay,
+            Err(e) => return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return StatusCode::NOT_FOUND.into_response()
+        }
+        Err(e) => {
+            log::error!("cannot read replay {}: {e}", path.display());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let speed = params.speed.unwrap_or(1.0);
+    if !(MIN_REPLAY_SPEED..=MAX_REPLAY_SPEED).contains(&speed) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("speed must be between {MIN_REPLAY_SPEED} and {MAX_REPLAY_SPEED}"),
+        )
+            .into_response();
+    }
+    let shutdown = app.shutdown.clone();
+    ws.on_upgrade(move |socket| play_replay(socket, name, replay, speed, shutdown))
+        .into_response()
+}
+
+async fn play_replay(
+    mut ws: WebSocket,
+    name: String,
+    replay: Replay,
+    speed: f32,
+    shutdown: CancellationToken,
+) {
+    let welcome = ServerMessage::Replay {
+        name: &name,
+        colors: TeamColors::default(),
+        tick_rate: replay.tick_rate,
+        speed,
+    };
+    if ws.send(welcome.to_message()).await.is_err() {
+        return;
+    }
+    let start = tokio::time::Instant::now();
+    for (offset, frame) in replay.frames() {
+        let at = start + offset.div_f32(speed);
+        tokio::select! {
+            _ = tokio::time::sleep_until(at) => {}
+            _ = shutdown.cancelled() => {
+                let _ = ws.send(shutdown::close_message()).await;
                 return;
             }
-            if Instant::now().duration_since(last_debug_print) > Duration::from_secs(1) {
-                last_debug_print = Instant::now();
-                log::debug!("Field positions: {:?}", field.positions());
-            }
-            field.update();
-            if field_updates.receiver_count() > 0 {
-                let _ = field_updates.send(FieldUpdates::Positions(field.positions()));
-            }
-            if Instant::now().duration_since(last_tick) > tick_duration {
-                last_tick = Instant::now();
-                if player_updates.sender_strong_count() > 0 {
-                    while let Ok(update) = player_updates.try_recv() {
-                        match update {
-                            PlayerUpdates::Position(id, y, x) => {
-                                if let Some(player) = field.players.get_mut(&id) {
-                                    player.update_target(y, x);
-                                }
-                            }
-                            PlayerUpdates::Join(token) => {
-                                let id = field.player_join();
-                                log::info!("Join request by {} as id {}", token, id);
-                                let _ = field_updates.send(FieldUpdates::Joined(token, id));
-                            }
-                            PlayerUpdates::Leave(id) => {
-                                field.player_leave(id);
-                                log::info!("Player {} left the field", id);
-                            }
-                        }
-                    }
-                }
-            }
+        }
+        if ws.send(Message::Binary(frame.to_vec())).await.is_err() {
+            return;
         }
     }
+    let _ = ws
+        .send(Message::Close(Some(CloseFrame {
+            code: close_code::NORMAL,
+            reason: "end of replay".into(),
+        })))
+        .await;
 }
 
-pub async fn get_field() -> impl IntoResponse {
-    let mut minij = minijinja::Environment::new();
-    minij.set_loader(path_loader("templates"));
-    let rendered = minij
-        .get_template("field.html")
-        .unwrap()
-        .render(context! {})
-        .unwrap();
-    Html::from(rendered)
+/// Joins the default room.
+pub async fn websocket_handler(
+    State(app): State<Arc<AppState>>,
+    Query(params): Query<JoinParams>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    join_room(app, DEFAULT_ROOM, params, ws)
 }
 
-pub async fn websocket_handler(
+pub async fn room_websocket_handler(
     State(app): State<Arc<AppState>>,
+    Path(room): Path<String>,
+    Query(params): Query<JoinParams>,
     ws: WebSocketUpgrade,
-) -> impl IntoResponse {
-    let thread = Arc::clone(&app.soccer_thread);
-    ws.on_upgrade(move |socket| handle_socket(socket, thread))
+) -> Response {
+    join_room(app, &room, params, ws)
 }
 
-async fn handle_socket(ws: WebSocket, soccer_thread: Arc<SoccerFieldThread>) {
-    let local_token: u16 = rand::thread_rng().gen();
-    // join field
-    let player_updates = soccer_thread.player_updates.clone();
-    player_updates
-        .send(PlayerUpdates::Join(local_token))
-        .await
-        .unwrap();
-    // wait for id
-    let mut receiver = soccer_thread.field_updates.subscribe();
-    let mut waiting = true;
-    let mut my_id = 0;
-    log::info!(
-        "client connected on soccerthread, joins with token {}",
-        local_token
-    );
-    while waiting {
-        if let Ok(FieldUpdates::Joined(token, id)) = receiver.recv().await {
-            if token == local_token {
-                log::info!("{} joined as id {}", local_token, id);
-                waiting = false;
-                my_id = id;
-            }
+fn join_room(app: Arc<AppState>, room: &str, params: JoinParams, ws: WebSocketUpgrade) -> Response {
+    let Some(rooms) = app.soccer_rooms.as_ref() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if params.fidelity == Fidelity::Low && !params.spectate {
+        return ValidationError::new(
+            "fidelity",
+            "only spectators can use the low fidelity stream",
+        )
+        .into_response();
+    }
+    let thread = match rooms.join(room, params.runtime) {
+        Ok(thread) => thread,
+        Err(e) => return e.into_response(),
+    };
+    let shutdown = app.shutdown.clone();
+    let max_rate = match params.fidelity {
+        Fidelity::Full => BROADCAST_RATE,
+        Fidelity::Low => LOW_FIDELITY_RATE,
+    };
+    let options = ClientOptions {
+        name: display_name(params.name),
+        rate: params.rate.unwrap_or(max_rate).clamp(1, max_rate),
+        protocol: params.protocol,
+        spectate: params.spectate,
+        fidelity: params.fidelity,
+    };
+    ws.max_message_size(MAX_MESSAGE_SIZE)
+        .on_upgrade(move |socket| handle_socket(socket, thread, options, shutdown))
+        .into_response()
+}
+
+/// Counts a websocket client of a field for as long as it lives.
+struct ClientGuard {
+    field: Arc<SoccerFieldThread>,
+    spectator: bool,
+}
+
+impl ClientGuard {
+    fn new(field: Arc<SoccerFieldThread>, spectator: bool) -> Self {
+        field.clients.fetch_add(1, Ordering::Relaxed);
+        if spectator {
+            field.spectators.fetch_add(1, Ordering::Relaxed);
         }
+        Self { field, spectator }
     }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.field.clients.fetch_sub(1, Ordering::Relaxed);
+        if self.spectator {
+            self.field.spectators.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Removes the player of a websocket client from the field however the
+/// connection ends, also when one of its tasks panicked.
+struct LeaveGuard {
+    id: u16,
+    player_updates: mpsc::Sender<PlayerUpdates>,
+}
+
+impl Drop for LeaveGuard {
+    fn drop(&mut self) {
+        let update = PlayerUpdates::Leave(self.id);
+        // the queue may be full, but the leave must not get lost
+        if let Err(mpsc::error::TrySendError::Full(update)) = self.player_updates.try_send(update) {
+            let player_updates = self.player_updates.clone();
+            tokio::spawn(async move {
+                let _ = player_updates.send(update).await;
+            });
+        }
+    }
+}
+
+async fn handle_socket(
+    ws: WebSocket,
+    soccer_thread: Arc<SoccerFieldThread>,
+    options: ClientOptions,
+    shutdown: CancellationToken,
+) {
+    let ClientOptions {
+        name,
+        rate,
+        protocol,
+        spectate,
+        fidelity,
+    } = options;
+    let _client = ClientGuard::new(Arc::clone(&soccer_thread), spectate);
+    let player_updates = soccer_thread.player_updates.clone();
+    // join field, spectators only watch
+    let player = if spectate {
+        log::info!("spectator connected ({:?}, {} Hz)", protocol, rate);
+        None
+    } else {
+        let (reply, joined) = oneshot::channel();
+        if player_updates
+            .send(PlayerUpdates::Join(name, reply))
+            .await
+            .is_err()
+        {
+            return;
+        }
+        let Ok((id, team)) = joined.await else {
+            return;
+        };
+        log::info!("client joined as id {} ({:?}, {} Hz)", id, protocol, rate);
+        Some((id, team))
+    };
+    let my_id = player.map(|(id, _)| id);
+    let _leave = my_id.map(|id| LeaveGuard {
+        id,
+        player_updates: player_updates.clone(),
+    });
     // split ws
     let (mut ws_send, mut ws_recv) = ws.split();
+    let welcome = match player {
+        Some((id, team)) => ServerMessage::Welcome {
+            id,
+            team,
+            colors: TeamColors::default(),
+            tick_rate: TICKRATE,
+            rate,
+            protocol,
+            runtime: soccer_thread.runtime(),
+        },
+        None => ServerMessage::Spectate {
+            colors: TeamColors::default(),
+            tick_rate: TICKRATE,
+            rate,
+            protocol,
+            fidelity,
+            runtime: soccer_thread.runtime(),
+        },
+    };
+    if ws_send.send(welcome.to_message()).await.is_err() {
+        return;
+    }
+    // errors found in client messages, answered by the server to client task
+    let (errors_tx, mut errors) = mpsc::channel::<ClientError>(8);
     // server to client task
+    let mut positions = soccer_thread.positions.clone();
+    let telemetry = Arc::clone(&soccer_thread.telemetry);
     let mut server_to_client_task = tokio::spawn(async move {
-        while let Ok(update) = receiver.recv().await {
-            if let FieldUpdates::Positions(poslist) = &update {
-                ws_send
-                    .send(Message::Text(serde_json::to_string(&poslist).unwrap()))
-                    .await
-                    .unwrap();
+        let period = Duration::from_secs(1) / rate as u32;
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut encoder = DeltaEncoder::default();
+        loop {
+            let message = tokio::select! {
+                _ = interval.tick() => {
+                    // only the latest state is sent, states in between are dropped
+                    match positions.has_changed() {
+                        Ok(true) => {}
+                        Ok(false) => continue,
+                        // the field thread stopped
+                        Err(_) => break,
+                    }
+                    let mut state = Arc::clone(&positions.borrow_and_update());
+                    if fidelity == Fidelity::Low {
+                        state = Arc::new(state.coarse());
+                    }
+                    match protocol {
+                        Protocol::Json => ServerMessage::Positions(&state).to_message(),
+                        Protocol::Binary => Message::Binary(encoder.encode(&state)),
+                    }
+                }
+                Some(error) = errors.recv() => ServerMessage::Error(&error).to_message(),
+                _ = shutdown.cancelled() => {
+                    let _ = ws_send.send(shutdown::close_message()).await;
+                    break;
+                }
+            };
+            // a client which stops reading would otherwise hold the task forever
+            let started = Instant::now();
+            match tokio::time::timeout(SEND_TIMEOUT, ws_send.send(message)).await {
+                Ok(Ok(())) if started.elapsed() > period => telemetry.lagged(),
+                Ok(Ok(())) => {}
+                Ok(Err(_)) => break,
+                Err(_) => {
+                    log::warn!("dropping client {:?} which does not keep up", my_id);
+                    break;
+                }
             }
         }
     });
@@ -344,29 +1180,54 @@ async fn handle_socket(ws: WebSocket, soccer_thread: Arc<SoccerFieldThread>) {
     // client to server task
 
     let mut client_to_server_task = tokio::spawn(async move {
+        let mut limiter = RateLimiter::new(Instant::now());
+        // only the first dropped target in a row is reported
+        let mut limited = false;
         while let Some(update) = ws_recv.next().await {
-            match update {
-                Ok(Message::Text(json)) => {
-                    let (yf, xf): (f32, f32) = serde_json::from_str(&json).unwrap();
-                    let (y, x): (i16, i16) = (yf.round() as i16, xf.round() as i16);
-                    let _ = player_updates
-                        .send(PlayerUpdates::Position(my_id, y, x))
-                        .await;
+            let error = match update {
+                Ok(Message::Text(text)) => {
+                    // targets of spectators are ignored
+                    let Some(my_id) = my_id else {
+                        continue;
+                    };
+                    if !limiter.allow(Instant::now()) {
+                        if std::mem::replace(&mut limited, true) {
+                            continue;
+                        }
+                        ClientError::new(
+                            ErrorCode::RateLimited,
+                            "too many targets, some were dropped",
+                        )
+                    } else {
+                        limited = false;
+                        match input::parse_target(&text) {
+                            Ok((y, x)) => {
+                                // the newest target replaces this one anyway,
+                                // so it is dropped rather than waiting for room
+                                let _ =
+                                    player_updates.try_send(PlayerUpdates::Position(my_id, y, x));
+                                continue;
+                            }
+                            Err(error) => error,
+                        }
+                    }
+                }
+                Ok(Message::Binary(_)) => {
+                    ClientError::new(ErrorCode::Unsupported, "binary messages are not supported")
                 }
                 Ok(Message::Close(_)) => {
-                    log::warn!("Socket for user '{}' closed by client", my_id);
-                    let _ = player_updates.send(PlayerUpdates::Leave(my_id)).await;
+                    log::info!("Socket for user '{:?}' closed by client", my_id);
                     return;
                 }
-                Ok(_) => {
-                    log::warn!("not implemented");
-                }
+                // pings are answered by axum
+                Ok(_) => continue,
                 Err(error) => {
-                    log::error!("websocket error: {:?}", error);
-                    let _ = player_updates.send(PlayerUpdates::Leave(my_id)).await;
+                    log::warn!("websocket error: {:?}", error);
                     return;
                 }
-            }
+            };
+            // a client which does not read its errors gets no more of them
+            let _ = errors_tx.try_send(error);
         }
     });
 
@@ -375,3 +1236,299 @@ async fn handle_socket(ws: WebSocket, soccer_thread: Arc<SoccerFieldThread>) {
         _ = &mut server_to_client_task => client_to_server_task.abort(),
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn player(position: Vec2, target: Vec2) -> Player {
+        Player {
+            team: Team::Home,
+            name: None,
+            spawn: position,
+            target,
+            position,
+            velocity: Vec2::default(),
+        }
+    }
+
+    #[test]
+    fn fixed_timestep_carries_remainder() {
+        let mut timestep = FixedTimestep::new(Duration::from_millis(10));
+        assert_eq!(timestep.advance(Duration::from_millis(25)), 2);
+        assert_eq!(timestep.until_next(), Duration::from_millis(5));
+        assert_eq!(timestep.advance(Duration::from_millis(5)), 1);
+        assert_eq!(timestep.advance(Duration::from_millis(9)), 0);
+    }
+
+    #[test]
+    fn fixed_timestep_caps_catch_up() {
+        let mut timestep = FixedTimestep::new(Duration::from_millis(10));
+        assert_eq!(
+            timestep.advance(Duration::from_secs(10)),
+            MAX_CATCH_UP_TICKS
+        );
+        assert_eq!(timestep.advance(Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn player_ids_wrap_around_without_reuse() {
+        let mut field = SoccerField::new();
+        let (first, _) = field.player_join(None);
+        for _ in 0..u16::MAX as u32 + 10 {
+            let (id, _) = field.player_join(None);
+            assert_ne!(id, first);
+            field.player_leave(id);
+        }
+        // the counter has wrapped past the first player
+        assert!(field.id_inc < 20);
+        let (second, _) = field.player_join(None);
+        let (third, _) = field.player_join(None);
+        assert_eq!(field.players.len(), 3);
+        assert!(second != first && third != first && second != third);
+    }
+
+    #[test]
+    fn joins_fill_the_smaller_team() {
+        let mut field = SoccerField::new();
+        let teams: Vec<Team> = (0..4).map(|_| field.player_join(None).1).collect();
+        assert_eq!(teams, [Team::Home, Team::Away, Team::Home, Team::Away]);
+        let home = field
+            .players
+            .iter()
+            .find(|(_, p)| p.team == Team::Home)
+            .map(|(id, _)| *id)
+            .unwrap();
+        field.player_leave(home);
+        assert_eq!(field.player_join(None).1, Team::Home);
+        assert_eq!(field.team_size(Team::Home), field.team_size(Team::Away));
+    }
+
+    #[test]
+    fn away_spawns_mirror_home_spawns() {
+        for slot in 0..FORMATION.len() + 1 {
+            let Coordinate(y, x) = Team::Home.spawn(slot);
+            assert_eq!(
+                Team::Away.spawn(slot),
+                Coordinate(FIELD_BOUNDARY_Y - y, FIELD_BOUNDARY_X - x)
+            );
+            // each team starts in its own half
+            assert!(y < FIELD_BOUNDARY_Y / 2);
+        }
+    }
+
+    #[test]
+    fn names_are_trimmed_and_printable() {
+        assert_eq!(
+            display_name(Some("a very long player name".into())).as_deref(),
+            Some("a very long play")
+        );
+        assert_eq!(
+            display_name(Some(" bell\u{7}\nboy ".into())).as_deref(),
+            Some("bellboy")
+        );
+        assert_eq!(display_name(Some("\t\r\n".into())), None);
+        assert_eq!(display_name(None), None);
+        // characters, not bytes
+        assert_eq!(
+            display_name(Some("ü".repeat(20))).unwrap().chars().count(),
+            16
+        );
+    }
+
+    #[test]
+    fn match_clock_depends_on_ticks_only() {
+        let mut field = SoccerField::new();
+        for _ in 0..TICKRATE {
+            field.step();
+        }
+        assert_eq!(field.clock.elapsed, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn player_speed_is_limited_and_reaches_target() {
+        let mut p = player(Vec2(100.0, 100.0), Vec2(700.0, 300.0));
+        for _ in 0..1000 {
+            p.update();
+            assert!(p.velocity.length() <= MAX_PLAYER_SPEED + 1e-4);
+        }
+        assert!((p.position - p.target).length() < 0.5, "{:?}", p.position);
+    }
+
+    #[test]
+    fn players_stay_on_the_field() {
+        let mut p = player(Vec2(20.0, 20.0), Vec2(-500.0, -500.0));
+        for _ in 0..100 {
+            p.update();
+        }
+        assert_eq!(p.position, Vec2(PLAYER_RADIUS, PLAYER_RADIUS));
+    }
+
+    #[test]
+    fn overlapping_players_are_separated() {
+        let mut a = player(Vec2(100.0, 100.0), Vec2(100.0, 100.0));
+        let mut b = player(Vec2(100.0, 105.0), Vec2(100.0, 105.0));
+        collide_players(&mut a, &mut b);
+        let distance = (b.position - a.position).length();
+        assert!((distance - 2.0 * PLAYER_RADIUS).abs() < 1e-4);
+        // b was to the right of a and stays there
+        assert!(b.position.1 > a.position.1);
+    }
+
+    #[test]
+    fn players_do_not_pass_through_each_other() {
+        let mut field = SoccerField::new();
+        let (a, _) = field.player_join(None);
+        let (b, _) = field.player_join(None);
+        field.players.get_mut(&a).unwrap().position = Vec2(200.0, 100.0);
+        field.players.get_mut(&b).unwrap().position = Vec2(200.0, 300.0);
+        field.players.get_mut(&a).unwrap().target = Vec2(200.0, 300.0);
+        field.players.get_mut(&b).unwrap().target = Vec2(200.0, 100.0);
+        for _ in 0..TICKRATE {
+            field.step();
+            let distance = (field.players[&b].position - field.players[&a].position).length();
+            assert!(distance >= 2.0 * PLAYER_RADIUS - 1e-3, "{distance}");
+        }
+        assert!(field.players[&a].position.1 < field.players[&b].position.1);
+    }
+
+    #[test]
+    fn kicked_ball_moves_away_within_max_speed() {
+        let mut p = player(Vec2(400.0, 180.0), Vec2(400.0, 400.0));
+        p.velocity = Vec2(0.0, MAX_PLAYER_SPEED);
+        let mut ball = Ball::kickoff();
+        ball.position = Vec2(400.0, 190.0);
+        collide_ball(&p, &mut ball);
+        assert!(ball.velocity.1 > MAX_PLAYER_SPEED);
+        assert!(ball.velocity.length() <= MAX_BALL_SPEED + 1e-4);
+        assert!((ball.position - p.position).length() >= PLAYER_RADIUS + BALL_RADIUS - 1e-4);
+    }
+
+    #[test]
+    fn ball_at_rest_is_pushed_not_kicked() {
+        let p = player(Vec2(400.0, 190.0), Vec2(400.0, 190.0));
+        let mut ball = Ball::kickoff();
+        collide_ball(&p, &mut ball);
+        assert_eq!(ball.velocity, Vec2::default());
+        assert!((ball.position - p.position).length() >= PLAYER_RADIUS + BALL_RADIUS - 1e-4);
+    }
+
+    #[test]
+    fn ball_bounces_off_the_side_lines() {
+        let mut ball = Ball::kickoff();
+        ball.velocity = Vec2(0.0, MAX_BALL_SPEED);
+        for _ in 0..100 {
+            ball.update();
+            assert!(ball.position.1 <= FIELD_BOUNDARY_X as f32 - BALL_RADIUS);
+        }
+        assert!(ball.velocity.1 < 0.0);
+    }
+
+    #[test]
+    fn ball_in_goal_mouth_scores() {
+        let mut field = SoccerField::new();
+        field.ball.position = Vec2(BALL_RADIUS, FIELD_BOUNDARY_X as f32 / 2.0);
+        assert_eq!(field.goal(), Some(Team::Away));
+        field.ball.position = Vec2(FIELD_BOUNDARY_Y as f32 - BALL_RADIUS, 200.0);
+        assert_eq!(field.goal(), Some(Team::Home));
+        field.ball.position = Vec2(BALL_RADIUS, BALL_RADIUS);
+        assert_eq!(field.goal(), None);
+    }
+
+    #[test]
+    fn goals_count_and_restart_from_kickoff() {
+        let mut field = SoccerField::new();
+        let (home, _) = field.player_join(None);
+        let (away, _) = field.player_join(None);
+        for (id, position) in [(home, Vec2(300.0, 50.0)), (away, Vec2(500.0, 350.0))] {
+            let player = field.players.get_mut(&id).unwrap();
+            player.position = position;
+            player.target = position;
+        }
+        field.ball.position = Vec2(BALL_RADIUS, FIELD_BOUNDARY_X as f32 / 2.0);
+        field.step();
+        assert_eq!(field.score, Score { home: 0, away: 1 });
+        assert_eq!(field.ball.position, Ball::kickoff().position);
+        assert_eq!(field.ball.velocity, Vec2::default());
+        for player in field.players.values() {
+            assert_eq!(player.position, player.spawn);
+            assert_eq!(player.target, player.spawn);
+            assert_eq!(player.velocity, Vec2::default());
+        }
+    }
+
+    #[test]
+    fn match_phases_follow_each_other() {
+        let mut clock = MatchClock::new();
+        assert_eq!(clock.advance(HALF_DURATION - tick_duration()), None);
+        assert_eq!(clock.advance(tick_duration()), Some(MatchPhase::HalfTime));
+        assert_eq!(clock.elapsed, Duration::ZERO);
+        assert_eq!(clock.advance(HALF_TIME_BREAK), Some(MatchPhase::SecondHalf));
+        assert_eq!(clock.advance(HALF_DURATION), Some(MatchPhase::FullTime));
+        assert_eq!(clock.advance(FULL_TIME_BREAK), Some(MatchPhase::FirstHalf));
+    }
+
+    #[test]
+    fn new_match_resets_the_score() {
+        let mut field = SoccerField::new();
+        let (id, _) = field.player_join(None);
+        field.players.get_mut(&id).unwrap().position = Vec2(300.0, 50.0);
+        field.score = Score { home: 3, away: 2 };
+        field.clock = MatchClock {
+            phase: MatchPhase::SecondHalf,
+            elapsed: HALF_DURATION - tick_duration(),
+        };
+        field.step();
+        assert_eq!(field.clock.phase, MatchPhase::FullTime);
+        // the result stays up during the break
+        assert_eq!(field.score, Score { home: 3, away: 2 });
+        field.clock.elapsed = FULL_TIME_BREAK - tick_duration();
+        field.step();
+        assert_eq!(field.clock.phase, MatchPhase::FirstHalf);
+        assert_eq!(field.score, Score::default());
+        assert_eq!(field.players[&id].position, field.players[&id].spawn);
+    }
+
+    #[test]
+    fn coarse_positions_snap_to_the_grid() {
+        let mut field = SoccerField::new();
+        let (id, _) = field.player_join(None);
+        field.players.get_mut(&id).unwrap().position = Vec2(101.0, 203.0);
+        field.ball.position = Vec2(396.0, 198.0);
+        let coarse = field.positions().coarse();
+        assert_eq!(coarse.players[&id].position, Coordinate(104, 200));
+        assert_eq!(coarse.ball, Coordinate(400, 200));
+    }
+
+    async fn post_bots(app: &Arc<AppState>, room: Option<&str>, count: u16) -> (StatusCode, Value) {
+        let form = BotsForm {
+            room: room.map(String::from),
+            count,
+            difficulty: Some(BotDifficulty::Easy),
+        };
+        let response = set_bots(State(Arc::clone(app)), Json(form)).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn bots_are_set_in_open_rooms_only() {
+        let app = AppState::for_tests(Config::default());
+        let (status, body) = post_bots(&app, None, 3).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"count": 3, "difficulty": "easy"}));
+
+        let (status, body) = post_bots(&app, None, MAX_BOTS + 1).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["field"], "count");
+        let (status, body) = post_bots(&app, Some("nowhere"), 1).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["field"], "room");
+        app.soccer_rooms.as_ref().unwrap().shutdown().await;
+    }
+}
@@ -0,0 +1,318 @@
+//! Server-side players. Bots join and leave like everyone else and are steered
+//! through `Player::update_target`, the same path as the websocket clients.
+//! Their decisions are made on the simulation thread, so every bot adds work
+//! per tick that grows with the number of players on the field.
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Coordinate, Player, SoccerField, Team, Vec2, BALL_RADIUS, FIELD_BOUNDARY_X, FIELD_BOUNDARY_Y,
+    PLAYER_RADIUS,
+};
+
+/// Highest number of bots on the field.
+pub const MAX_BOTS: u16 = 64;
+
+/// How far ahead of the ball a bot aims when it runs into it.
+const KICK_DEPTH: f32 = 20.0;
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BotDifficulty {
+    /// Chases the ball.
+    Easy,
+    /// The bot closest to the ball shoots at the goal, the others defend.
+    #[default]
+    Medium,
+    /// Like medium, but passes to a team mate closer to the goal.
+    Hard,
+}
+
+impl FromStr for BotDifficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Self::Easy),
+            "medium" => Ok(Self::Medium),
+            "hard" => Ok(Self::Hard),
+            _ => Err("expected easy, medium or hard".into()),
+        }
+    }
+}
+
+impl BotDifficulty {
+    /// Ticks between two decisions of a bot.
+    fn reaction_ticks(&self) -> u64 {
+        match self {
+            BotDifficulty::Easy => 32,
+            BotDifficulty::Medium => 8,
+            BotDifficulty::Hard => 2,
+        }
+    }
+}
+
+/// Bots on the field, the answer to `POST /soccer_field/bots`.
+#[derive(Clone, Debug, Serialize)]
+pub struct BotsInfo {
+    pub count: u16,
+    pub difficulty: BotDifficulty,
+}
+
+impl Team {
+    /// Center of the goal the team defends.
+    fn goal(&self) -> Vec2 {
+        let x = FIELD_BOUNDARY_X as f32 / 2.0;
+        match self {
+            Team::Home => Vec2(0.0, x),
+            Team::Away => Vec2(FIELD_BOUNDARY_Y as f32, x),
+        }
+    }
+
+    fn opponent(&self) -> Team {
+        match self {
+            Team::Home => Team::Away,
+            Team::Away => Team::Home,
+        }
+    }
+}
+
+impl SoccerField {
+    /// Adds or removes bots until there are `count`, the newest leave first.
+    pub(super) fn set_bots(&mut self, count: u16, difficulty: Option<BotDifficulty>) -> BotsInfo {
+        if let Some(difficulty) = difficulty {
+            self.bot_difficulty = difficulty;
+        }
+        while self.bots.len() > count as usize {
+            if let Some(id) = self.bots.pop_last() {
+                self.player_leave(id);
+            }
+        }
+        while self.bots.len() < count as usize {
+            let (id, _) = self.player_join(None);
+            if let Some(player) = self.players.get_mut(&id) {
+                player.name = Some(format!("bot {id}"));
+            }
+            self.bots.insert(id);
+        }
+        BotsInfo {
+            count: self.bots.len() as u16,
+            difficulty: self.bot_difficulty,
+        }
+    }
+
+    pub(super) fn steer_bots(&mut self) {
+        let reaction_ticks = self.bot_difficulty.reaction_ticks();
+        let targets: Vec<(u16, Vec2)> = self
+            .bots
+            .iter()
+            // spread the decisions of the bots over the ticks
+            .filter(|id| (self.tick + **id as u64).is_multiple_of(reaction_ticks))
+            .filter_map(|id| Some((*id, self.bot_target(self.players.get(id)?, *id))))
+            .collect();
+        for (id, target) in targets {
+            let Coordinate(y, x) = target.into();
+            if let Some(player) = self.players.get_mut(&id) {
+                player.update_target(y, x);
+            }
+        }
+    }
+
+    fn bot_target(&self, bot: &Player, id: u16) -> Vec2 {
+        let ball = self.ball.position;
+        if self.bot_difficulty == BotDifficulty::Easy {
+            return ball;
+        }
+        let closest = self
+            .players
+            .iter()
+            .filter(|(_, p)| p.team == bot.team)
+            .min_by(|(_, a), (_, b)| {
+                let a = (a.position - ball).length();
+                let b = (b.position - ball).length();
+                a.total_cmp(&b)
+            })
+            .map(|(id, _)| *id);
+        if closest != Some(id) {
+            // between the ball and the own goal, keeping some of the kickoff
+            // formation so defenders do not pile up
+            let goal = bot.team.goal();
+            return Vec2(
+                goal.0 + (ball.0 - goal.0) * 0.3,
+                (bot.spawn.1 + ball.1) / 2.0,
+            );
+        }
+        let goal = bot.team.opponent().goal();
+        let aim = match self.bot_difficulty {
+            BotDifficulty::Hard => self.pass_target(bot, id, goal).unwrap_or(goal),
+            _ => goal,
+        };
+        kick_target(bot.position, ball, aim)
+    }
+
+    /// The team mate closest to the opponent goal, if it is clearly closer than `bot`.
+    fn pass_target(&self, bot: &Player, id: u16, goal: Vec2) -> Option<Vec2> {
+        let own_distance = (bot.position - goal).length();
+        self.players
+            .iter()
+            .filter(|(other, p)| p.team == bot.team && **other != id)
+            .map(|(_, p)| (p.position, (p.position - goal).length()))
+            .filter(|(_, distance)| *distance < own_distance - 100.0)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(position, _)| position)
+    }
+}
+
+/// Where to run to push the ball towards `aim`.
+fn kick_target(position: Vec2, ball: Vec2, aim: Vec2) -> Vec2 {
+    let direction = (aim - ball).normalize_or(Vec2(1.0, 0.0));
+    let reach = PLAYER_RADIUS + BALL_RADIUS;
+    let to_ball = ball - position;
+    if to_ball.dot(direction) < 0.0 {
+        // in front of the ball, running at it would push it the wrong way
+        let side = Vec2(-direction.1, direction.0);
+        let side = if (position - ball).dot(side) >= 0.0 {
+            side
+        } else {
+            side * -1.0
+        };
+        return ball - direction * (2.0 * reach) + side * (2.0 * reach);
+    }
+    if to_ball.normalize_or(direction).dot(direction) > 0.9 {
+        // lined up behind the ball, run through it
+        ball + direction * KICK_DEPTH
+    } else {
+        ball - direction * reach
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ids of the bots of `team`, oldest first.
+    fn team(field: &SoccerField, team: Team) -> Vec<u16> {
+        let mut ids: Vec<u16> = field
+            .bots
+            .iter()
+            .copied()
+            .filter(|id| field.players[id].team == team)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    fn place(field: &mut SoccerField, id: u16, position: Vec2) {
+        let player = field.players.get_mut(&id).unwrap();
+        player.position = position;
+        player.target = position;
+    }
+
+    #[test]
+    fn newest_bots_leave_first() {
+        let mut field = SoccerField::new();
+        let info = field.set_bots(3, Some(BotDifficulty::Hard));
+        assert_eq!((info.count, info.difficulty), (3, BotDifficulty::Hard));
+        let (human, _) = field.player_join(Some("human".into()));
+        let first = *field.bots.first().unwrap();
+        assert_eq!(
+            field.players[&first].name.as_deref(),
+            Some(&*format!("bot {first}"))
+        );
+
+        let info = field.set_bots(1, None);
+        assert_eq!((info.count, info.difficulty), (1, BotDifficulty::Hard));
+        assert_eq!(field.bots.iter().copied().collect::<Vec<_>>(), [first]);
+        assert!(field.players.contains_key(&human));
+        assert_eq!(field.players.len(), 2);
+
+        field.set_bots(0, None);
+        assert!(field.bots.is_empty());
+        assert_eq!(field.players.keys().copied().collect::<Vec<_>>(), [human]);
+    }
+
+    #[test]
+    fn full_field_of_bots_is_split_evenly() {
+        let mut field = SoccerField::new();
+        assert_eq!(field.set_bots(MAX_BOTS, None).count, MAX_BOTS);
+        assert_eq!(field.players.len(), MAX_BOTS as usize);
+        assert_eq!(field.team_size(Team::Home), MAX_BOTS as usize / 2);
+    }
+
+    #[test]
+    fn easy_bots_chase_the_ball() {
+        let mut field = SoccerField::new();
+        field.set_bots(4, Some(BotDifficulty::Easy));
+        field.ball.position = Vec2(150.0, 120.0);
+        for id in field.bots.clone() {
+            let bot = &field.players[&id];
+            assert_eq!(field.bot_target(bot, id), field.ball.position);
+        }
+    }
+
+    #[test]
+    fn medium_bots_send_only_the_closest_to_the_ball() {
+        let mut field = SoccerField::new();
+        field.set_bots(4, Some(BotDifficulty::Medium));
+        let home = team(&field, Team::Home);
+        let ball = Vec2(400.0, 200.0);
+        field.ball.position = ball;
+        place(&mut field, home[0], Vec2(380.0, 200.0));
+        place(&mut field, home[1], Vec2(200.0, 100.0));
+
+        let attacker = &field.players[&home[0]];
+        let target = field.bot_target(attacker, home[0]);
+        let goal = Team::Away.goal();
+        assert_eq!(target, kick_target(attacker.position, ball, goal));
+
+        // the other one stays between the ball and the own goal
+        let defender = &field.players[&home[1]];
+        let Vec2(y, _) = field.bot_target(defender, home[1]);
+        assert!(y > 0.0 && y < ball.0, "{y}");
+    }
+
+    #[test]
+    fn hard_bots_pass_to_team_mates_closer_to_the_goal() {
+        let mut field = SoccerField::new();
+        field.set_bots(4, Some(BotDifficulty::Hard));
+        let home = team(&field, Team::Home);
+        let ball = Vec2(300.0, 200.0);
+        field.ball.position = ball;
+        place(&mut field, home[0], Vec2(280.0, 200.0));
+        place(&mut field, home[1], Vec2(650.0, 120.0));
+
+        let bot = &field.players[&home[0]];
+        let goal = Team::Away.goal();
+        assert_eq!(
+            field.pass_target(bot, home[0], goal),
+            Some(Vec2(650.0, 120.0))
+        );
+        assert_eq!(
+            field.bot_target(bot, home[0]),
+            kick_target(bot.position, ball, Vec2(650.0, 120.0))
+        );
+
+        // not clearly closer to the goal, shoot instead
+        place(&mut field, home[1], Vec2(320.0, 120.0));
+        let bot = &field.players[&home[0]];
+        assert_eq!(field.pass_target(bot, home[0], goal), None);
+        assert_eq!(
+            field.bot_target(bot, home[0]),
+            kick_target(bot.position, ball, goal)
+        );
+    }
+
+    #[test]
+    fn kick_target_is_behind_the_ball() {
+        let ball = Vec2(400.0, 200.0);
+        let aim = Vec2(800.0, 200.0);
+        // lined up, run through the ball towards the goal
+        let target = kick_target(Vec2(300.0, 200.0), ball, aim);
+        assert_eq!(target, Vec2(400.0 + KICK_DEPTH, 200.0));
+        // in front of the ball, go around it
+        let target = kick_target(Vec2(500.0, 200.0), ball, aim);
+        assert!(target.0 < ball.0, "{target:?}");
+    }
+}
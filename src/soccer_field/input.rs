@@ -0,0 +1,124 @@
+//! Messages from the websocket clients. A player sends its target as `[y, x]`
+//! within the field, anything else is answered with an `error` message and
+//! otherwise ignored. Targets beyond `MAX_TARGET_RATE` per second are dropped,
+//! so a single client cannot fill up the update queue of a field.
+
+use std::time::Instant;
+
+use serde::Serialize;
+
+use super::{FIELD_BOUNDARY_X, FIELD_BOUNDARY_Y};
+
+/// Largest websocket message a client may send, a target takes about 12 bytes.
+pub const MAX_MESSAGE_SIZE: usize = 256;
+/// Targets per second a player may send on average.
+const MAX_TARGET_RATE: f32 = 30.0;
+/// Targets a player may send at once after being quiet.
+const TARGET_BURST: f32 = 10.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Not a `[y, x]` pair of numbers.
+    InvalidMessage,
+    /// Target outside of the field.
+    OutOfField,
+    /// Too many targets, the ones above the limit were dropped.
+    RateLimited,
+    /// Binary messages are not understood.
+    Unsupported,
+}
+
+/// Body of an `error` message to a client.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClientError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ClientError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Parses a target sent by a player.
+pub fn parse_target(text: &str) -> Result<(i16, i16), ClientError> {
+    let (y, x): (f32, f32) = serde_json::from_str(text).map_err(|_| {
+        ClientError::new(
+            ErrorCode::InvalidMessage,
+            "expected the target as [y, x] numbers",
+        )
+    })?;
+    let on_field = (0.0..=FIELD_BOUNDARY_Y as f32).contains(&y)
+        && (0.0..=FIELD_BOUNDARY_X as f32).contains(&x);
+    if !on_field {
+        return Err(ClientError::new(
+            ErrorCode::OutOfField,
+            format!("y must be 0 to {FIELD_BOUNDARY_Y} and x 0 to {FIELD_BOUNDARY_X}"),
+        ));
+    }
+    Ok((y.round() as i16, x.round() as i16))
+}
+
+/// Token bucket for the targets of one player.
+pub struct RateLimiter {
+    tokens: f32,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            tokens: TARGET_BURST,
+            last: now,
+        }
+    }
+
+    /// Whether a target received at `now` is within the limit.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        let refill = now.duration_since(self.last).as_secs_f32() * MAX_TARGET_RATE;
+        self.tokens = (self.tokens + refill).min(TARGET_BURST);
+        self.last = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn targets_are_range_checked() {
+        assert_eq!(parse_target("[100.4, 399.6]").unwrap(), (100, 400));
+        assert_eq!(parse_target("[0, 0]").unwrap(), (0, 0));
+        let code = |text| parse_target(text).unwrap_err().code;
+        assert_eq!(code("[-1, 200]"), ErrorCode::OutOfField);
+        assert_eq!(code("[400, 401]"), ErrorCode::OutOfField);
+        assert_eq!(code("[1e30, 0]"), ErrorCode::OutOfField);
+        assert_eq!(code("[1, 2, 3]"), ErrorCode::InvalidMessage);
+        assert_eq!(code("{\"y\": 1}"), ErrorCode::InvalidMessage);
+        assert_eq!(code("hello"), ErrorCode::InvalidMessage);
+    }
+
+    #[test]
+    fn limiter_allows_bursts_and_refills() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(start);
+        let allowed = (0..100).filter(|_| limiter.allow(start)).count();
+        assert_eq!(allowed, TARGET_BURST as usize);
+        // one target every 1 / MAX_TARGET_RATE seconds keeps passing
+        let later = start + Duration::from_secs_f32(1.0 / MAX_TARGET_RATE);
+        assert!(limiter.allow(later));
+        assert!(!limiter.allow(later));
+    }
+}
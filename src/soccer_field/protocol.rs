@@ -0,0 +1,275 @@
+//! Binary frames of `/soccer_field/ws?protocol=binary`.
+//!
+//! The first frame after the JSON welcome is a snapshot of the whole field,
+//! every later frame only holds what changed since the previous frame sent to
+//! the same client. All numbers are little endian.
+//!
+//! ```text
+//! header    kind: u8 (1 snapshot, 2 delta), tick: u32
+//! score     home: u16, away: u16
+//! clock     phase: u8 (first half, half time, second half, full time),
+//!           elapsed_secs: u16, duration_secs: u16
+//! position  y: i16, x: i16
+//! player    id: u16, team: u8 (0 home, 1 away), position, name_len: u8, name: utf-8
+//!
+//! snapshot  header, score, clock, spectators: u16, ball position,
+//!           count: u16, player * count
+//! delta     header, flags: u8 (1 score, 2 clock, 4 ball, 8 spectators),
+//!           score, clock, spectators and ball position if flagged,
+//!           removed: u16, id: u16 * removed,
+//!           added: u16, player * added,
+//!           moved: u16, (id: u16, position) * moved
+//! ```
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ClockState, Coordinate, MatchPhase, PlayerState, PositionsList, Score, Team};
+
+const SNAPSHOT: u8 = 1;
+const DELTA: u8 = 2;
+
+const SCORE_CHANGED: u8 = 1;
+const CLOCK_CHANGED: u8 = 2;
+const BALL_CHANGED: u8 = 4;
+const SPECTATORS_CHANGED: u8 = 8;
+
+/// Format of the position updates, chosen with `?protocol=` on connect.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// Full `positions` JSON messages.
+    #[default]
+    Json,
+    /// Snapshot and delta frames as described in the module docs.
+    Binary,
+}
+
+/// Encodes the states sent to one client, a delta is always relative to the
+/// previous state given to `encode`.
+#[derive(Default)]
+pub struct DeltaEncoder {
+    last: Option<Arc<PositionsList>>,
+}
+
+impl DeltaEncoder {
+    pub fn encode(&mut self, state: &Arc<PositionsList>) -> Vec<u8> {
+        let frame = match &self.last {
+            None => snapshot(state),
+            Some(last) => delta(last, state),
+        };
+        self.last = Some(Arc::clone(state));
+        frame
+    }
+}
+
+fn snapshot(state: &PositionsList) -> Vec<u8> {
+    let mut out = header(SNAPSHOT, state.tick);
+    put_score(&mut out, &state.score);
+    put_clock(&mut out, &state.clock);
+    put_u16(&mut out, state.spectators);
+    put_position(&mut out, state.ball);
+    put_u16(&mut out, state.players.len() as u16);
+    for (id, player) in &state.players {
+        put_player(&mut out, *id, player);
+    }
+    out
+}
+
+fn delta(last: &PositionsList, state: &PositionsList) -> Vec<u8> {
+    let mut out = header(DELTA, state.tick);
+    let mut flags = 0;
+    if last.score != state.score {
+        flags |= SCORE_CHANGED;
+    }
+    if last.clock != state.clock {
+        flags |= CLOCK_CHANGED;
+    }
+    if last.ball != state.ball {
+        flags |= BALL_CHANGED;
+    }
+    if last.spectators != state.spectators {
+        flags |= SPECTATORS_CHANGED;
+    }
+    out.push(flags);
+    if flags & SCORE_CHANGED != 0 {
+        put_score(&mut out, &state.score);
+    }
+    if flags & CLOCK_CHANGED != 0 {
+        put_clock(&mut out, &state.clock);
+    }
+    if flags & SPECTATORS_CHANGED != 0 {
+        put_u16(&mut out, state.spectators);
+    }
+    if flags & BALL_CHANGED != 0 {
+        put_position(&mut out, state.ball);
+    }
+
+    let removed: Vec<u16> = last
+        .players
+        .keys()
+        .filter(|id| !state.players.contains_key(id))
+        .copied()
+        .collect();
+    put_u16(&mut out, removed.len() as u16);
+    for id in removed {
+        put_u16(&mut out, id);
+    }
+
+    let added: Vec<(&u16, &PlayerState)> = state
+        .players
+        .iter()
+        .filter(|(id, _)| !last.players.contains_key(id))
+        .collect();
+    put_u16(&mut out, added.len() as u16);
+    for (id, player) in added {
+        put_player(&mut out, *id, player);
+    }
+
+    let moved: Vec<(&u16, &PlayerState)> = state
+        .players
+        .iter()
+        .filter(|(id, player)| {
+            last.players
+                .get(id)
+                .is_some_and(|before| before.position != player.position)
+        })
+        .collect();
+    put_u16(&mut out, moved.len() as u16);
+    for (id, player) in moved {
+        put_u16(&mut out, *id);
+        put_position(&mut out, player.position);
+    }
+    out
+}
+
+fn header(kind: u8, tick: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(64);
+    out.push(kind);
+    // wraps after 388 days at 128 ticks per second
+    out.extend_from_slice(&(tick as u32).to_le_bytes());
+    out
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_position(out: &mut Vec<u8>, Coordinate(y, x): Coordinate) {
+    out.extend_from_slice(&y.to_le_bytes());
+    out.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put_score(out: &mut Vec<u8>, score: &Score) {
+    put_u16(out, score.home);
+    put_u16(out, score.away);
+}
+
+fn put_clock(out: &mut Vec<u8>, clock: &ClockState) {
+    out.push(match clock.phase {
+        MatchPhase::FirstHalf => 0,
+        MatchPhase::HalfTime => 1,
+        MatchPhase::SecondHalf => 2,
+        MatchPhase::FullTime => 3,
+    });
+    put_u16(out, clock.elapsed_secs as u16);
+    put_u16(out, clock.duration_secs as u16);
+}
+
+fn put_player(out: &mut Vec<u8>, id: u16, player: &PlayerState) {
+    put_u16(out, id);
+    out.push(match player.team {
+        Team::Home => 0,
+        Team::Away => 1,
+    });
+    put_position(out, player.position);
+    // names are at most MAX_NAME_LEN characters, so they always fit
+    let name = player.name.as_deref().unwrap_or_default().as_bytes();
+    out.push(name.len() as u8);
+    out.extend_from_slice(name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::soccer_field::SoccerField;
+
+    fn state(field: &SoccerField) -> Arc<PositionsList> {
+        Arc::new(field.positions())
+    }
+
+    #[test]
+    fn first_frame_is_a_snapshot() {
+        let mut field = SoccerField::new();
+        field.player_join(Some("bob".into()));
+        let frame = DeltaEncoder::default().encode(&state(&field));
+        // header, score, clock, spectators, ball, count, one player with a
+        // three byte name
+        assert_eq!(frame.len(), 5 + 4 + 5 + 2 + 4 + 2 + (2 + 1 + 4 + 1 + 3));
+        assert_eq!(frame[0], SNAPSHOT);
+        assert_eq!(&frame[frame.len() - 3..], b"bob");
+    }
+
+    #[test]
+    fn unchanged_state_is_an_empty_delta() {
+        let mut field = SoccerField::new();
+        field.player_join(None);
+        let mut encoder = DeltaEncoder::default();
+        encoder.encode(&state(&field));
+        let frame = encoder.encode(&state(&field));
+        assert_eq!(frame, [DELTA, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn spectator_count_is_flagged() {
+        let mut field = SoccerField::new();
+        let mut encoder = DeltaEncoder::default();
+        encoder.encode(&state(&field));
+        field.spectators = 3;
+        let frame = encoder.encode(&state(&field));
+        assert_eq!(
+            frame,
+            [
+                DELTA,
+                0,
+                0,
+                0,
+                0,
+                SPECTATORS_CHANGED,
+                3,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0
+            ]
+        );
+    }
+
+    #[test]
+    fn delta_holds_joined_left_and_moved_players() {
+        let mut field = SoccerField::new();
+        let (stays, _) = field.player_join(None);
+        let (leaves, _) = field.player_join(None);
+        let mut encoder = DeltaEncoder::default();
+        encoder.encode(&state(&field));
+
+        field.player_leave(leaves);
+        let (joins, _) = field.player_join(None);
+        field.players.get_mut(&stays).unwrap().position.0 += 10.0;
+        let frame = encoder.encode(&state(&field));
+
+        let mut expected = vec![DELTA, 0, 0, 0, 0, 0];
+        expected.extend_from_slice(&1u16.to_le_bytes());
+        expected.extend_from_slice(&leaves.to_le_bytes());
+        expected.extend_from_slice(&1u16.to_le_bytes());
+        put_player(&mut expected, joins, &field.positions().players[&joins]);
+        expected.extend_from_slice(&1u16.to_le_bytes());
+        expected.extend_from_slice(&stays.to_le_bytes());
+        put_position(&mut expected, field.positions().players[&stays].position);
+        assert_eq!(frame, expected);
+    }
+}
@@ -0,0 +1,262 @@
+//! Recording and playback of matches. A replay file holds the binary frames of
+//! `protocol.rs` as a client with the `binary` protocol would have received
+//! them, so joins, leaves and goals are part of the deltas.
+//!
+//! ```text
+//! header  magic: b"SFR1", tick_rate: u16
+//! record  length: u32, frame: [u8; length]
+//! ```
+//!
+//! A recording starts with the first player on the field and ends at full
+//! time, when the field is empty again or when the room is closed.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::{protocol::DeltaEncoder, MatchPhase, SoccerField, TICKRATE};
+
+const MAGIC: &[u8; 4] = b"SFR1";
+const EXTENSION: &str = "replay";
+/// Frames per second in a recording.
+const RECORD_RATE: u16 = 32;
+/// Recordings of one room which can start within the same second.
+const MAX_RECORDINGS_PER_SECOND: u32 = 1000;
+
+/// Writes the matches of one room into `<dir>/<room>-<start time>.replay`.
+pub struct Recorder {
+    dir: PathBuf,
+    room: String,
+    file: Option<BufWriter<File>>,
+    encoder: DeltaEncoder,
+    next_tick: u64,
+    frames: u64,
+}
+
+impl Recorder {
+    pub fn new(dir: &Path, room: &str) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            room: room.to_string(),
+            file: None,
+            encoder: DeltaEncoder::default(),
+            next_tick: 0,
+            frames: 0,
+        }
+    }
+
+    /// Called after every tick, records every `TICKRATE / RECORD_RATE`th one.
+    pub fn record(&mut self, field: &SoccerField) {
+        if field.tick < self.next_tick {
+            return;
+        }
+        self.next_tick = field.tick + (TICKRATE / RECORD_RATE) as u64;
+        if field.players.is_empty() || field.clock.phase == MatchPhase::FullTime {
+            self.finish();
+            return;
+        }
+        if self.file.is_none() {
+            match self.create() {
+                Ok(file) => self.file = Some(file),
+                Err(e) => {
+                    log::error!("cannot start a replay in {}: {e}", self.dir.display());
+                    // try again in a second instead of on every frame
+                    self.next_tick = field.tick + TICKRATE as u64;
+                    return;
+                }
+            }
+            self.encoder = DeltaEncoder::default();
+        }
+        let frame = self.encoder.encode(&Arc::new(field.positions()));
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        self.frames += 1;
+        let written = file
+            .write_all(&(frame.len() as u32).to_le_bytes())
+            .and_then(|_| file.write_all(&frame))
+            // a running match can be watched up to the last second
+            .and_then(|_| match self.frames.is_multiple_of(RECORD_RATE as u64) {
+                true => file.flush(),
+                false => Ok(()),
+            });
+        if let Err(e) = written {
+            log::error!("cannot write replay of room '{}': {e}", self.room);
+            self.file = None;
+        }
+    }
+
+    /// Ends the current recording, if there is one.
+    pub fn finish(&mut self) {
+        if let Some(mut file) = self.file.take() {
+            if let Err(e) = file.flush() {
+                log::error!("cannot write replay of room '{}': {e}", self.room);
+            }
+            log::info!("replay of room '{}' finished", self.room);
+        }
+    }
+
+    fn create(&self) -> std::io::Result<BufWriter<File>> {
+        std::fs::create_dir_all(&self.dir)?;
+        let stem = format!("{}-{}", self.room, Utc::now().format("%Y%m%dT%H%M%S"));
+        // a new recording can start within the same second as the last one
+        for n in 0..MAX_RECORDINGS_PER_SECOND {
+            let name = match n {
+                0 => stem.clone(),
+                n => format!("{stem}-{n}"),
+            };
+            let path = self.dir.join(&name).with_extension(EXTENSION);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    log::info!("recording room '{}' to {}", self.room, path.display());
+                    let mut file = BufWriter::new(file);
+                    file.write_all(MAGIC)?;
+                    file.write_all(&TICKRATE.to_le_bytes())?;
+                    return Ok(file);
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("no free replay name for {stem}"),
+        ))
+    }
+}
+
+/// Entry of `GET /soccer_field/replays`.
+#[derive(Serialize)]
+pub struct ReplayInfo {
+    name: String,
+    bytes: u64,
+    modified: Option<DateTime<Utc>>,
+}
+
+/// Recordings in `dir`, newest first.
+pub fn list(dir: &Path) -> std::io::Result<Vec<ReplayInfo>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // nothing recorded yet
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut replays = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(EXTENSION) {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let metadata = std::fs::metadata(&path)?;
+        replays.push(ReplayInfo {
+            name: name.to_string(),
+            bytes: metadata.len(),
+            modified: metadata.modified().ok().map(DateTime::from),
+        });
+    }
+    replays.sort_by_key(|replay| std::cmp::Reverse(replay.modified));
+    Ok(replays)
+}
+
+/// Path of the replay `name`, `None` if the name could leave `dir`.
+pub fn path(dir: &Path, name: &str) -> Option<PathBuf> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| dir.join(name).with_extension(EXTENSION))
+}
+
+/// A recording read back into memory.
+pub struct Replay {
+    pub tick_rate: u16,
+    data: Vec<u8>,
+}
+
+impl Replay {
+    pub fn parse(data: Vec<u8>) -> Result<Self, String> {
+        if data.len() < 6 || &data[..4] != MAGIC {
+            return Err("not a replay file".into());
+        }
+        let tick_rate = u16::from_le_bytes([data[4], data[5]]);
+        if tick_rate == 0 {
+            return Err("replay without tick rate".into());
+        }
+        Ok(Self { tick_rate, data })
+    }
+
+    /// Frames with their time after the first frame, a truncated last
+    /// frame of a recording that is still running is left out.
+    pub fn frames(&self) -> impl Iterator<Item = (Duration, &[u8])> {
+        let mut rest = &self.data[6..];
+        let mut first_tick = None;
+        let tick = Duration::from_secs(1) / self.tick_rate as u32;
+        std::iter::from_fn(move || {
+            let length = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+            let frame = rest.get(4..4 + length)?;
+            rest = &rest[4 + length..];
+            let frame_tick = u32::from_le_bytes(frame.get(1..5)?.try_into().ok()?);
+            let first = *first_tick.get_or_insert(frame_tick);
+            Some((tick * frame_tick.wrapping_sub(first), frame))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(data: &mut Vec<u8>, tick: u32) {
+        let frame = [&[2u8][..], &tick.to_le_bytes()].concat();
+        data.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        data.extend_from_slice(&frame);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(Replay::parse(b"{}".to_vec()).is_err());
+        assert!(Replay::parse([&MAGIC[..], &[0, 0]].concat()).is_err());
+    }
+
+    #[test]
+    fn frames_are_timed_by_tick() {
+        let mut data = [&MAGIC[..], &128u16.to_le_bytes()].concat();
+        record(&mut data, 1000);
+        record(&mut data, 1004);
+        record(&mut data, 1128);
+        // a frame the recorder was still writing
+        data.extend_from_slice(&[5, 0, 0, 0, 2, 0]);
+        let replay = Replay::parse(data).unwrap();
+        let offsets: Vec<Duration> = replay.frames().map(|(offset, _)| offset).collect();
+        assert_eq!(
+            offsets,
+            [
+                Duration::ZERO,
+                Duration::from_secs(1) / 32,
+                Duration::from_secs(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn recordings_started_together_get_their_own_file() {
+        let dir = std::env::temp_dir().join(format!("replays-{}", std::process::id()));
+        let recorder = Recorder::new(&dir, "main");
+        let first = recorder.create().unwrap();
+        let second = recorder.create().unwrap();
+        drop((first, second));
+        let files = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files, 2);
+    }
+}
@@ -0,0 +1,276 @@
+//! Every room runs its own field simulation. Rooms are spawned when the first
+//! client joins and stopped after they had no clients for
+//! `soccer_room_idle_secs`, only the default room stays up all the time.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use super::{telemetry::Window, SoccerFieldThread, SoccerRuntime, TelemetrySample};
+use crate::config::Config;
+
+/// Room of `/soccer_field/ws`, it also gets the `soccer_bots` at startup.
+pub const DEFAULT_ROOM: &str = "main";
+const MAX_ROOM_NAME_LEN: usize = 32;
+
+pub struct Rooms {
+    rooms: Mutex<HashMap<String, Room>>,
+    config: Arc<Config>,
+}
+
+struct Room {
+    field: Arc<SoccerFieldThread>,
+    /// When the last client left, `None` while there are clients.
+    idle_since: Option<Instant>,
+}
+
+/// Entry of `GET /soccer_field/rooms`.
+#[derive(Serialize)]
+pub struct RoomInfo {
+    name: String,
+    players: usize,
+    clients: usize,
+    spectators: usize,
+    runtime: SoccerRuntime,
+}
+
+#[derive(Debug)]
+pub enum RoomError {
+    InvalidName,
+    TooManyRooms(usize),
+}
+
+impl IntoResponse for RoomError {
+    fn into_response(self) -> Response {
+        match self {
+            RoomError::InvalidName => (
+                StatusCode::BAD_REQUEST,
+                format!("room names are 1 to {MAX_ROOM_NAME_LEN} letters, digits, '-' or '_'"),
+            )
+                .into_response(),
+            RoomError::TooManyRooms(max) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("all {max} rooms are in use"),
+            )
+                .into_response(),
+        }
+    }
+}
+
+impl Rooms {
+    pub fn new(config: Arc<Config>) -> Self {
+        let default = Room {
+            field: Arc::new(SoccerFieldThread::spawn(
+                DEFAULT_ROOM,
+                config.soccer_bots,
+                config.soccer_runtime,
+                &config,
+            )),
+            idle_since: None,
+        };
+        Self {
+            rooms: Mutex::new(HashMap::from([(DEFAULT_ROOM.to_string(), default)])),
+            config,
+        }
+    }
+
+    /// The field of room `name`, spawned on `runtime` if it does not exist
+    /// yet, `soccer_runtime` by default.
+    pub fn join(
+        &self,
+        name: &str,
+        runtime: Option<SoccerRuntime>,
+    ) -> Result<Arc<SoccerFieldThread>, RoomError> {
+        let valid = (1..=MAX_ROOM_NAME_LEN).contains(&name.len())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(RoomError::InvalidName);
+        }
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(room) = rooms.get_mut(name) {
+            // keeps the reaper away until the client is counted
+            room.idle_since = None;
+            return Ok(Arc::clone(&room.field));
+        }
+        let max_rooms = self.config.soccer_max_rooms;
+        if rooms.len() >= max_rooms {
+            return Err(RoomError::TooManyRooms(max_rooms));
+        }
+        let runtime = runtime.unwrap_or(self.config.soccer_runtime);
+        log::info!("opening soccer room '{name}' ({runtime:?})");
+        let field = Arc::new(SoccerFieldThread::spawn(name, 0, runtime, &self.config));
+        let room = Room {
+            field: Arc::clone(&field),
+            idle_since: None,
+        };
+        rooms.insert(name.to_string(), room);
+        Ok(field)
+    }
+
+    /// The field of room `name`, if it is open.
+    pub fn get(&self, name: &str) -> Option<Arc<SoccerFieldThread>> {
+        let rooms = self.rooms.lock().unwrap();
+        rooms.get(name).map(|room| Arc::clone(&room.field))
+    }
+
+    pub fn list(&self) -> Vec<RoomInfo> {
+        let rooms = self.rooms.lock().unwrap();
+        let mut list: Vec<RoomInfo> = rooms
+            .iter()
+            .map(|(name, room)| RoomInfo {
+                name: name.clone(),
+                players: room.field.players(),
+                clients: room.field.clients(),
+                spectators: room.field.spectators(),
+                runtime: room.field.runtime(),
+            })
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    /// Timing of all simulations since the last call.
+    pub fn telemetry(&self) -> TelemetrySample {
+        let rooms = self.rooms.lock().unwrap();
+        let mut window = Window::default();
+        for room in rooms.values() {
+            window.merge(&room.field.telemetry.take());
+        }
+        window.sample(rooms.len())
+    }
+
+    /// Stops rooms which had no clients for the idle timeout, until shutdown.
+    pub async fn reap_idle(self: Arc<Self>, shutdown: CancellationToken) {
+        let idle_timeout = Duration::from_secs(self.config.soccer_room_idle_secs);
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.cancelled() => return,
+            }
+            let idle = {
+                let mut rooms = self.rooms.lock().unwrap();
+                let now = Instant::now();
+                for room in rooms.values_mut() {
+                    if room.field.clients() > 0 {
+                        room.idle_since = None;
+                    } else {
+                        room.idle_since.get_or_insert(now);
+                    }
+                }
+                let idle: Vec<String> = rooms
+                    .iter()
+                    .filter(|(name, room)| {
+                        name.as_str() != DEFAULT_ROOM
+                            && room
+                                .idle_since
+                                .is_some_and(|since| now - since >= idle_timeout)
+                    })
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                idle.into_iter()
+                    .filter_map(|name| rooms.remove(&name).map(|room| (name, room)))
+                    .collect::<Vec<_>>()
+            };
+            for (name, room) in idle {
+                log::info!("closing idle soccer room '{name}'");
+                room.field.shutdown().await;
+            }
+        }
+    }
+
+    /// Stops all rooms.
+    pub async fn shutdown(&self) {
+        let rooms: Vec<Room> = self.rooms.lock().unwrap().drain().map(|(_, r)| r).collect();
+        for room in rooms {
+            room.field.shutdown().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rooms(config: Config) -> Arc<Rooms> {
+        Arc::new(Rooms::new(Arc::new(config)))
+    }
+
+    #[tokio::test]
+    async fn room_names_are_checked() {
+        let rooms = rooms(Config::default());
+        for name in [
+            "",
+            &"a".repeat(MAX_ROOM_NAME_LEN + 1),
+            "two words",
+            "ünicode",
+            "a/b",
+        ] {
+            assert!(
+                matches!(rooms.join(name, None), Err(RoomError::InvalidName)),
+                "{name:?}"
+            );
+        }
+        assert!(rooms.join(&"a".repeat(MAX_ROOM_NAME_LEN), None).is_ok());
+        assert!(rooms.join("Side-room_2", None).is_ok());
+        rooms.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn rooms_are_limited() {
+        let rooms = rooms(Config {
+            soccer_max_rooms: 2,
+            ..Config::default()
+        });
+        assert!(rooms.join("second", None).is_ok());
+        let error = rooms.join("third", None).err().unwrap();
+        assert!(matches!(error, RoomError::TooManyRooms(2)));
+        assert_eq!(
+            error.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        // open rooms can still be joined
+        assert!(rooms.join("second", None).is_ok());
+        assert!(rooms.join(DEFAULT_ROOM, None).is_ok());
+        rooms.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn idle_rooms_are_closed_except_the_default_room() {
+        let rooms = rooms(Config {
+            soccer_room_idle_secs: 1,
+            ..Config::default()
+        });
+        rooms.join("side", None).unwrap();
+        let shutdown = CancellationToken::new();
+        let reaper = tokio::spawn(Arc::clone(&rooms).reap_idle(shutdown.clone()));
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(
+            rooms.get("side").is_some(),
+            "closed before the idle timeout"
+        );
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while rooms.get("side").is_some() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await
+        .expect("idle room was not closed");
+        // idle just as long, but the default room stays
+        assert!(rooms.get(DEFAULT_ROOM).is_some());
+        shutdown.cancel();
+        reaper.await.unwrap();
+        rooms.shutdown().await;
+    }
+}
@@ -0,0 +1,326 @@
+//! Execution models of the field simulation. The same loop runs on a dedicated
+//! OS thread which sleeps until the next tick, as a task on the main tokio
+//! runtime woken by an interval, or as such a task on a current-thread runtime
+//! of its own thread. Only the task on the main runtime shares its workers with
+//! the handlers and load generators, so blocking them delays its ticks.
+
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{mpsc, oneshot, oneshot::error::TryRecvError, watch},
+    time::MissedTickBehavior,
+};
+
+use super::{
+    replay::Recorder, telemetry::Telemetry, tick_duration, FixedTimestep, PlayerUpdates,
+    PositionsList, SoccerField, BROADCAST_RATE,
+};
+
+/// Where the simulation of a room runs, `soccer_runtime` or `?runtime=`.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SoccerRuntime {
+    /// Dedicated OS thread polling the update queue between ticks.
+    #[default]
+    Thread,
+    /// Task on the main runtime, next to the handlers and load generators.
+    Task,
+    /// Task on a current-thread runtime with its own OS thread.
+    CurrentThread,
+}
+
+impl FromStr for SoccerRuntime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "thread" => Ok(Self::Thread),
+            "task" => Ok(Self::Task),
+            "current_thread" => Ok(Self::CurrentThread),
+            _ => Err("expected thread, task or current_thread".into()),
+        }
+    }
+}
+
+/// State of the simulation loop, shared by all execution models.
+pub(super) struct Simulation {
+    pub field: SoccerField,
+    pub recorder: Option<Recorder>,
+    pub positions: watch::Sender<Arc<PositionsList>>,
+    pub player_updates: mpsc::Receiver<PlayerUpdates>,
+    pub players: Arc<AtomicUsize>,
+    pub spectators: Arc<AtomicUsize>,
+    pub telemetry: Arc<Telemetry>,
+}
+
+/// The running simulation of a room.
+pub(super) enum Runner {
+    /// Closed when the thread is done, its sender is dropped on return and
+    /// on a panic alike.
+    Thread(oneshot::Receiver<()>),
+    Task(tokio::task::JoinHandle<()>),
+}
+
+impl Runner {
+    /// Starts `simulation` on `runtime`, it stops when `exit` fires.
+    pub fn spawn(
+        runtime: SoccerRuntime,
+        room: &str,
+        simulation: Simulation,
+        exit: oneshot::Receiver<bool>,
+    ) -> Self {
+        let thread = std::thread::Builder::new().name(format!("soccer-{room}"));
+        // joining the thread would need one of the few blocking threads
+        // for as long as the simulation takes to stop
+        let (done, joined) = oneshot::channel::<()>();
+        match runtime {
+            SoccerRuntime::Thread => {
+                thread
+                    .spawn(move || {
+                        let _done = done;
+                        run_thread(simulation, exit);
+                    })
+                    .expect("cannot spawn soccer field thread");
+                Runner::Thread(joined)
+            }
+            SoccerRuntime::Task => Runner::Task(tokio::spawn(run_task(simulation, exit))),
+            SoccerRuntime::CurrentThread => {
+                thread
+                    .spawn(move || {
+                        let _done = done;
+                        let runtime = tokio::runtime::Builder::new_current_thread()
+                            .enable_time()
+                            .build()
+                            .expect("cannot build soccer field runtime");
+                        runtime.block_on(run_task(simulation, exit));
+                    })
+                    .expect("cannot spawn soccer field thread");
+                Runner::Thread(joined)
+            }
+        }
+    }
+
+    /// Waits for the simulation to stop.
+    pub async fn join(self) {
+        match self {
+            Runner::Thread(joined) => {
+                let _ = joined.await;
+            }
+            Runner::Task(handle) => {
+                let _ = handle.await;
+            }
+        }
+    }
+}
+
+fn run_thread(mut simulation: Simulation, mut exit: oneshot::Receiver<bool>) {
+    let mut clock = LoopClock::new();
+    loop {
+        // a dropped sender means nobody can stop the room anymore, like the
+        // task runners stop on it as well
+        if let Ok(_) | Err(TryRecvError::Closed) = exit.try_recv() {
+            simulation.finish();
+            return;
+        }
+        while let Ok(update) = simulation.player_updates.try_recv() {
+            simulation.apply(update);
+        }
+        let now = Instant::now();
+        let wake_up = simulation.advance(&mut clock, now);
+        std::thread::sleep(wake_up.saturating_duration_since(now));
+    }
+}
+
+async fn run_task(mut simulation: Simulation, mut exit: oneshot::Receiver<bool>) {
+    let mut clock = LoopClock::new();
+    let mut interval = tokio::time::interval(tick_duration());
+    // a late tick is made up by the fixed timestep, not by a burst of wake ups
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        tokio::select! {
+            _ = &mut exit => break,
+            Some(update) = simulation.player_updates.recv() => simulation.apply(update),
+            _ = interval.tick() => {
+                // replays are written from here, the file is buffered so this
+                // rarely blocks the runtime
+                simulation.advance(&mut clock, Instant::now());
+            }
+        }
+    }
+    simulation.finish();
+}
+
+/// Timing of the simulation loop.
+struct LoopClock {
+    timestep: FixedTimestep,
+    last_update: Instant,
+    next_broadcast: Instant,
+    last_debug_print: Instant,
+}
+
+impl LoopClock {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            timestep: FixedTimestep::new(tick_duration()),
+            last_update: now,
+            next_broadcast: now,
+            last_debug_print: now,
+        }
+    }
+}
+
+impl Simulation {
+    fn apply(&mut self, update: PlayerUpdates) {
+        let field = &mut self.field;
+        match update {
+            PlayerUpdates::Position(id, y, x) => {
+                if let Some(player) = field.players.get_mut(&id) {
+                    player.update_target(y, x);
+                }
+            }
+            PlayerUpdates::Join(name, reply) => {
+                let (id, team) = field.player_join(name);
+                log::info!("Player {} joined the field ({:?})", id, team);
+                let _ = reply.send((id, team));
+            }
+            PlayerUpdates::Leave(id) => {
+                field.player_leave(id);
+                log::info!("Player {} left the field", id);
+            }
+            PlayerUpdates::Bots(count, difficulty, reply) => {
+                let bots = field.set_bots(count, difficulty);
+                log::info!("{} {:?} bots on the field", bots.count, bots.difficulty);
+                let _ = reply.send(bots);
+            }
+        }
+        self.players.store(field.players.len(), Ordering::Relaxed);
+    }
+
+    /// Runs the ticks and the broadcast due at `now`, returns when the next one
+    /// is due.
+    fn advance(&mut self, clock: &mut LoopClock, now: Instant) -> Instant {
+        let field = &mut self.field;
+        field.spectators = self
+            .spectators
+            .load(Ordering::Relaxed)
+            .min(u16::MAX as usize) as u16;
+        let due = clock.last_update + clock.timestep.until_next();
+        // the simulation only depends on the number of ticks, not on how late
+        // the loop was woken up
+        let ticks = clock.timestep.advance(now - clock.last_update);
+        if ticks > 0 {
+            self.telemetry.lateness(now.saturating_duration_since(due));
+        }
+        for _ in 0..ticks {
+            let started = Instant::now();
+            field.step();
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record(field);
+            }
+            self.telemetry.tick(started.elapsed());
+        }
+        clock.last_update = now;
+        if now >= clock.next_broadcast {
+            // skip missed broadcasts instead of sending a burst
+            let broadcast_interval = Duration::from_secs(1) / BROADCAST_RATE as u32;
+            clock.next_broadcast = (clock.next_broadcast + broadcast_interval).max(now);
+            if self.positions.receiver_count() > 1 {
+                let started = Instant::now();
+                self.positions.send_replace(Arc::new(field.positions()));
+                self.telemetry.broadcast(started.elapsed());
+            }
+        }
+        if now.duration_since(clock.last_debug_print) > Duration::from_secs(1) {
+            clock.last_debug_print = now;
+            log::debug!("Field positions: {:?}", field.positions());
+        }
+        (now + clock.timestep.until_next()).min(clock.next_broadcast)
+    }
+
+    fn finish(&mut self) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A simulation without players and the receiving end of its positions.
+    fn simulation() -> (Simulation, watch::Receiver<Arc<PositionsList>>) {
+        let field = SoccerField::new();
+        let (positions, receiver) = watch::channel(Arc::new(field.positions()));
+        let (_updates, player_updates) = mpsc::channel(1);
+        let simulation = Simulation {
+            field,
+            recorder: None,
+            positions,
+            player_updates,
+            players: Arc::default(),
+            spectators: Arc::default(),
+            telemetry: Arc::default(),
+        };
+        (simulation, receiver)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn every_runtime_steps_broadcasts_and_exits() {
+        let runtimes = [
+            SoccerRuntime::Thread,
+            SoccerRuntime::Task,
+            SoccerRuntime::CurrentThread,
+        ];
+        // exit on a sent signal and on a sender dropped without one
+        for (runtime, dropped) in runtimes.into_iter().flat_map(|r| [(r, false), (r, true)]) {
+            let (simulation, receiver) = simulation();
+            // positions are only published with a client besides this receiver
+            let mut client = receiver.clone();
+            let (exit, exit_rx) = oneshot::channel();
+            let runner = Runner::spawn(runtime, "test", simulation, exit_rx);
+            tokio::time::timeout(Duration::from_secs(1), client.changed())
+                .await
+                .expect("no broadcast")
+                .unwrap();
+            if dropped {
+                drop(exit);
+            } else {
+                let _ = exit.send(true);
+            }
+            tokio::time::timeout(Duration::from_secs(1), runner.join())
+                .await
+                .unwrap_or_else(|_| panic!("{runtime:?} runner did not stop, dropped: {dropped}"));
+        }
+    }
+
+    #[test]
+    fn threads_are_joined_without_blocking_threads() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .max_blocking_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            // the only blocking thread is busy, like with a running blocker job
+            tokio::task::spawn_blocking(|| std::thread::sleep(Duration::from_secs(3)));
+            let (simulation, _receiver) = simulation();
+            let (exit, exit_rx) = oneshot::channel();
+            let runner = Runner::spawn(SoccerRuntime::Thread, "test", simulation, exit_rx);
+            let _ = exit.send(true);
+            tokio::time::timeout(Duration::from_secs(1), runner.join())
+                .await
+                .expect("join waited for a blocking thread");
+        });
+        runtime.shutdown_background();
+    }
+}
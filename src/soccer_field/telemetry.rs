@@ -0,0 +1,161 @@
+//! Timing of the simulation loops, read by the `soccer` stats source. Every
+//! room collects into its own window, which is emptied whenever it is sampled.
+//!
+//! - tick: time `SoccerField::step` took, replay recording included
+//! - lateness: how long after it was due a tick started, jitter is its
+//!   standard deviation
+//! - broadcast: time to build and publish the positions for the clients
+//! - lagged: updates which took a client longer to send than its update
+//!   interval
+
+use std::{sync::Mutex, time::Duration};
+
+use serde::Serialize;
+
+#[derive(Default)]
+pub struct Telemetry {
+    window: Mutex<Window>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Stat {
+    count: u64,
+    /// Milliseconds.
+    sum: f64,
+    sum_sq: f64,
+    max: f64,
+}
+
+impl Stat {
+    fn record(&mut self, duration: Duration) {
+        let ms = duration.as_secs_f64() * 1000.0;
+        self.count += 1;
+        self.sum += ms;
+        self.sum_sq += ms * ms;
+        self.max = self.max.max(ms);
+    }
+
+    fn merge(&mut self, other: &Stat) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.sum_sq += other.sum_sq;
+        self.max = self.max.max(other.max);
+    }
+
+    fn mean(&self) -> f64 {
+        match self.count {
+            0 => 0.0,
+            count => self.sum / count as f64,
+        }
+    }
+
+    fn std_dev(&self) -> f64 {
+        match self.count {
+            0 => 0.0,
+            count => (self.sum_sq / count as f64 - self.mean().powi(2))
+                .max(0.0)
+                .sqrt(),
+        }
+    }
+}
+
+/// Everything recorded since the last sample.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Window {
+    tick: Stat,
+    lateness: Stat,
+    broadcast: Stat,
+    lagged: u64,
+}
+
+impl Window {
+    pub fn merge(&mut self, other: &Window) {
+        self.tick.merge(&other.tick);
+        self.lateness.merge(&other.lateness);
+        self.broadcast.merge(&other.broadcast);
+        self.lagged += other.lagged;
+    }
+
+    pub fn sample(&self, rooms: usize) -> TelemetrySample {
+        // microsecond precision is plenty for a chart
+        let ms = |value: f64| (value * 1000.0).round() / 1000.0;
+        TelemetrySample {
+            rooms,
+            ticks: self.tick.count,
+            tick_ms: ms(self.tick.mean()),
+            tick_max_ms: ms(self.tick.max),
+            late_ms: ms(self.lateness.mean()),
+            late_max_ms: ms(self.lateness.max),
+            jitter_ms: ms(self.lateness.std_dev()),
+            broadcast_ms: ms(self.broadcast.mean()),
+            broadcast_max_ms: ms(self.broadcast.max),
+            lagged: self.lagged,
+        }
+    }
+}
+
+/// The `soccer` object of the stats stream.
+#[derive(Debug, Serialize)]
+pub struct TelemetrySample {
+    rooms: usize,
+    ticks: u64,
+    tick_ms: f64,
+    tick_max_ms: f64,
+    late_ms: f64,
+    late_max_ms: f64,
+    jitter_ms: f64,
+    broadcast_ms: f64,
+    broadcast_max_ms: f64,
+    lagged: u64,
+}
+
+impl Telemetry {
+    pub fn tick(&self, duration: Duration) {
+        self.window.lock().unwrap().tick.record(duration);
+    }
+
+    pub fn lateness(&self, duration: Duration) {
+        self.window.lock().unwrap().lateness.record(duration);
+    }
+
+    pub fn broadcast(&self, duration: Duration) {
+        self.window.lock().unwrap().broadcast.record(duration);
+    }
+
+    pub fn lagged(&self) {
+        self.window.lock().unwrap().lagged += 1;
+    }
+
+    /// The window since the last call, the next one starts empty.
+    pub fn take(&self) -> Window {
+        std::mem::take(&mut *self.window.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_merge_into_one_sample() {
+        let a = Telemetry::default();
+        a.lateness(Duration::from_millis(1));
+        a.lateness(Duration::from_millis(3));
+        a.lagged();
+        let b = Telemetry::default();
+        b.lateness(Duration::from_millis(2));
+        b.tick(Duration::from_micros(250));
+
+        let mut window = a.take();
+        window.merge(&b.take());
+        let sample = window.sample(2);
+        assert_eq!(sample.ticks, 1);
+        assert_eq!(sample.tick_ms, 0.25);
+        assert_eq!(sample.late_ms, 2.0);
+        assert_eq!(sample.late_max_ms, 3.0);
+        assert_eq!(sample.jitter_ms, 0.816);
+        assert_eq!(sample.lagged, 1);
+        // taking empties the window
+        assert_eq!(a.take().sample(1).lagged, 0);
+    }
+}
@@ -1,8 +1,16 @@
-use redis::{InfoDict, RedisResult};
-use serde_json::{json, Value};
-use std::{collections::VecDeque, sync::Arc, time::Duration};
-use sysinfo::{CpuExt, ProcessExt, SystemExt};
-use tokio::{sync::RwLock, task::JoinHandle, time::Interval};
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::RwLock, task::JoinHandle, time::Instant, time::Interval};
+
+use crate::{
+    history::{self, HistorySink},
+    metric_source::{Registration, Sample},
+};
 
 #[derive(Clone)]
 pub struct Stats {
@@ -25,36 +33,62 @@ impl Stats {
     async fn all(&self) -> VecDeque<Value> {
         self.data.read().await.clone()
     }
+    async fn latest(&self) -> Option<Value> {
+        self.data.read().await.back().cloned()
+    }
 }
 
 pub struct StatsCollector {
     _interval: Duration,
     stats: Stats,
+    sink: Option<Arc<HistorySink>>,
     bc: tokio::sync::broadcast::Sender<Value>,
-    _shutdown: tokio::sync::oneshot::Sender<bool>,
+    shutdown: Mutex<Option<tokio::sync::oneshot::Sender<bool>>>,
     _updater_handle: JoinHandle<()>,
 }
 
 impl StatsCollector {
-    pub fn new(interval: Duration, capacity: usize) -> StatsCollector {
+    pub fn new(
+        interval: Duration,
+        capacity: usize,
+        sink: Option<Arc<HistorySink>>,
+        sources: Vec<Registration>,
+    ) -> StatsCollector {
         let (bc_tx, _) = tokio::sync::broadcast::channel::<Value>(capacity);
         let (shutdown, shutdown_rx) = tokio::sync::oneshot::channel::<bool>();
         let bc = bc_tx.clone();
         let stats = Stats::new(capacity);
         let updater_interval = tokio::time::interval(interval);
         let updater_stats = stats.clone();
+        let updater_sink = sink.clone();
         let updater_handle = tokio::spawn(async move {
-            Self::updater(bc_tx, shutdown_rx, updater_stats, updater_interval).await;
+            Self::updater(
+                bc_tx,
+                shutdown_rx,
+                updater_stats,
+                updater_sink,
+                sources,
+                updater_interval,
+            )
+            .await;
         });
         StatsCollector {
             _interval: interval,
             stats,
+            sink,
             bc,
-            _shutdown: shutdown,
+            shutdown: Mutex::new(Some(shutdown)),
             _updater_handle: updater_handle,
         }
     }
 
+    /// Stops the updater, subscribers see the broadcast channel closing.
+    pub fn shutdown(&self) {
+        if let Some(shutdown) = self.shutdown.lock().ok().and_then(|mut s| s.take()) {
+            let _ = shutdown.send(true);
+        }
+    }
+
     pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<Value> {
         self.bc.subscribe()
     }
@@ -63,82 +97,173 @@ impl StatsCollector {
         self.stats.all().await
     }
 
+    pub async fn latest(&self) -> Option<Value> {
+        self.stats.latest().await
+    }
+
+    /// At most `limit` samples between `from` and `to`, read from the on-disk
+    /// sink if there is one, otherwise from the in-memory buffer. Without `from`
+    /// the newest samples are kept.
+    pub async fn history(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: usize,
+    ) -> Result<Vec<Value>, String> {
+        if let Some(sink) = &self.sink {
+            return sink.read(from, to, limit).await;
+        }
+        let samples: Vec<Value> = self
+            .stats
+            .all()
+            .await
+            .into_iter()
+            .filter(|sample| history::in_range(sample, from, to))
+            .collect();
+        let skip = match from {
+            Some(_) => 0,
+            None => samples.len().saturating_sub(limit),
+        };
+        Ok(samples.into_iter().skip(skip).take(limit).collect())
+    }
+
     async fn updater(
         bc: tokio::sync::broadcast::Sender<Value>,
         mut shutdown_rx: tokio::sync::oneshot::Receiver<bool>,
         stats: Stats,
+        sink: Option<Arc<HistorySink>>,
+        sources: Vec<Registration>,
         mut interval: Interval,
     ) {
-        log::info!("Stats collector task starting");
-        let metrics = tokio::runtime::Handle::current().metrics();
-        let current_pid = sysinfo::get_current_pid().expect("cannot get pid");
-        let url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1:6379".into());
-        let client = redis::Client::open(url).expect("cannot create redis client");
-        let mut conn = client.get_async_connection().await;
-        let mut system = sysinfo::System::new_all();
-        system.refresh_process(current_pid);
-        system.refresh_cpu();
-        system.refresh_memory();
+        log::info!(
+            "Stats collector task starting with sources: {}",
+            sources
+                .iter()
+                .map(|r| format!("{} ({:?})", r.source.name(), r.interval))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        // every source keeps its last sample until it is due again, `None`
+        // before the first one
+        let mut sources: Vec<(Registration, Option<Instant>, Sample)> = sources
+            .into_iter()
+            .map(|r| (r, None, Sample::new()))
+            .collect();
+        // a source which takes longer than a tick keeps its last sample, so a
+        // hanging redis connection doesn't hold up the others
+        let timeout = interval.period();
         loop {
-            if let Ok(true) = shutdown_rx.try_recv() {
-                log::info!("Closing stats updater");
-                return;
-            }
-
-            interval.tick().await;
-            system.refresh_process(current_pid);
-            system.refresh_cpu();
-            system.refresh_memory();
-            let process = system
-                .process(current_pid)
-                .expect("cannot get current process from system");
-            let tasks = metrics.num_alive_tasks();
-            let sync_threads = metrics.num_blocking_threads();
-            let now = chrono::Utc::now();
-            let memory = process.memory() / (1024 * 1024);
-            let memory_sys = system.used_memory() / (1024 * 1024);
-            let cpu_global = system.global_cpu_info().cpu_usage();
-            let cpu_process =
-                ((process.cpu_usage() / system.cpus().len() as f32) * 1000.0).round() / 1000.0;
-            let keys = if let Ok(ref mut conn) = conn {
-                let response: RedisResult<InfoDict> =
-                    redis::cmd("INFO").arg("KEYSPACE").query_async(conn).await;
-                get_redis_keys_from_result(&response)
-            } else {
-                0
+            let tick = tokio::select! {
+                _ = &mut shutdown_rx => {
+                    log::info!("Closing stats updater");
+                    return;
+                }
+                tick = interval.tick() => tick,
             };
-            let message = json!({
-                "time": now.to_rfc3339(),
-                "tasks": tasks,
-                "sync_threads": sync_threads,
-                "mem": memory_sys,
-                "mem_proc": memory,
-                "cpu": cpu_global,
-                "cpu_proc": cpu_process,
-                "keys": keys
-            });
+            let now = chrono::Utc::now();
+            let mut message = Map::new();
+            message.insert("time".into(), now.to_rfc3339().into());
+            let due = sources
+                .iter_mut()
+                .filter(|(_, next, _)| next.is_none_or(|next| tick >= next))
+                .map(|(registration, next, last)| async move {
+                    // relative to the tick, so a source due every tick is
+                    // sampled on every tick
+                    *next = Some(tick + registration.interval);
+                    let name = registration.source.name();
+                    match tokio::time::timeout(timeout, registration.source.sample()).await {
+                        Ok(sample) => *last = sample,
+                        Err(_) => log::warn!("stats source {name} timed out"),
+                    }
+                });
+            futures::future::join_all(due).await;
+            for (_, _, last) in &sources {
+                message.extend(last.clone());
+            }
+            let message = Value::Object(message);
             log::trace!("{:?}", message);
             stats.push(message.clone()).await;
+            if let Some(sink) = &sink {
+                sink.write(&message).await;
+            }
             let _ = bc.send(message);
         }
     }
 }
 
-fn get_redis_keys_from_result(response: &RedisResult<InfoDict>) -> u64 {
-    if let Ok(response) = response {
-        let db0: Option<String> = response.get("db0");
-        if let Some(db0) = db0 {
-            // unwraps "keys=123,bla=123,blabla=123..."
-            let x: Option<u64> = db0
-                .split_once(',')
-                .map(|(x, _)| x.split_once('=').map(|(_, x)| x.parse().ok()))
-                .unwrap_or(Some(Some(0)))
-                .unwrap_or(Some(0));
-            x.unwrap_or(0)
-        } else {
-            0
+#[cfg(test)]
+mod tests {
+    use futures::{future::BoxFuture, FutureExt};
+    use serde_json::json;
+
+    use super::*;
+    use crate::metric_source::MetricSource;
+
+    struct Counter(u64);
+
+    impl MetricSource for Counter {
+        fn name(&self) -> &'static str {
+            "counter"
+        }
+
+        fn sample(&mut self) -> BoxFuture<'_, Sample> {
+            self.0 += 1;
+            let sample = json!({ "count": self.0 });
+            futures::future::ready(sample.as_object().unwrap().clone()).boxed()
+        }
+    }
+
+    /// Like a redis server which doesn't answer.
+    struct Hanging;
+
+    impl MetricSource for Hanging {
+        fn name(&self) -> &'static str {
+            "hanging"
+        }
+
+        fn sample(&mut self) -> BoxFuture<'_, Sample> {
+            futures::future::pending().boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn hanging_sources_dont_hold_up_the_others() {
+        let interval = Duration::from_millis(20);
+        let collector = StatsCollector::new(
+            interval,
+            16,
+            None,
+            vec![
+                Registration::new(Hanging, interval),
+                Registration::new(Counter(0), interval),
+            ],
+        );
+        let mut messages = collector.subscribe();
+        let mut counts = vec![];
+        for _ in 0..4 {
+            let message = messages.recv().await.unwrap();
+            assert!(message.get("hanging").is_none());
+            counts.push(message["count"].as_u64().unwrap());
+        }
+        // sampled on every tick, not every other one
+        assert_eq!(counts, [1, 2, 3, 4]);
+        collector.shutdown();
+    }
+
+    #[tokio::test]
+    async fn sources_keep_their_sample_between_intervals() {
+        let collector = StatsCollector::new(
+            Duration::from_millis(20),
+            16,
+            None,
+            vec![Registration::new(Counter(0), Duration::from_millis(60))],
+        );
+        let mut messages = collector.subscribe();
+        let mut counts = vec![];
+        for _ in 0..7 {
+            counts.push(messages.recv().await.unwrap()["count"].as_u64().unwrap());
         }
-    } else {
-        0
+        assert_eq!(counts, [1, 1, 1, 2, 2, 2, 3]);
+        collector.shutdown();
     }
 }
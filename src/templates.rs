@@ -0,0 +1,192 @@
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use minijinja::{path_loader, Environment};
+use minijinja_autoreload::AutoReloader;
+use serde::{Deserialize, Serialize};
+
+/// Templates checked at startup.
+const TEMPLATES: &[&str] = &["base.html", "field.html"];
+
+/// Where templates come from, set with `templates` in the config.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateMode {
+    /// Loaded from `<assets_dir>/templates` on first use and cached.
+    Path,
+    /// Loaded from `<assets_dir>/templates` and reloaded when a file changes.
+    Reload,
+    /// Compiled into the binary, needs the `embed` feature.
+    Embedded,
+}
+
+impl FromStr for TemplateMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(Self::Path),
+            "reload" => Ok(Self::Reload),
+            "embedded" => Ok(Self::Embedded),
+            _ => Err("expected path, reload or embedded".into()),
+        }
+    }
+}
+
+pub enum Templates {
+    Cached(Environment<'static>),
+    Reload(AutoReloader),
+}
+
+impl Templates {
+    pub fn new(mode: TemplateMode, dir: PathBuf) -> Result<Self, String> {
+        let templates = match mode {
+            TemplateMode::Path => {
+                let mut env = Environment::new();
+                env.set_loader(path_loader(dir));
+                Self::Cached(env)
+            }
+            TemplateMode::Reload => Self::Reload(AutoReloader::new(move |notifier| {
+                let mut env = Environment::new();
+                env.set_loader(path_loader(&dir));
+                notifier.watch_path(&dir, true);
+                Ok(env)
+            })),
+            #[cfg(feature = "embed")]
+            TemplateMode::Embedded => {
+                let mut env = Environment::new();
+                env.set_loader(|name| {
+                    Ok(crate::assets::EmbeddedTemplates::get(name)
+                        .map(|file| String::from_utf8_lossy(&file.data).into_owned()))
+                });
+                Self::Cached(env)
+            }
+            // rejected when the config is validated
+            #[cfg(not(feature = "embed"))]
+            TemplateMode::Embedded => return Err("built without the embed feature".into()),
+        };
+        // a missing or broken template fails at startup, not on the first request
+        for name in TEMPLATES {
+            templates
+                .with_env(|env| env.get_template(name).map(|_| ()))
+                .map_err(|e| format!("cannot load template {name}: {e}"))?;
+        }
+        log::info!("serving templates in {mode:?} mode");
+        Ok(templates)
+    }
+
+    fn with_env<T>(
+        &self,
+        f: impl FnOnce(&Environment<'static>) -> Result<T, minijinja::Error>,
+    ) -> Result<T, minijinja::Error> {
+        match self {
+            Templates::Cached(env) => f(env),
+            Templates::Reload(reloader) => f(&*reloader.acquire_env()?),
+        }
+    }
+
+    pub fn render(&self, name: &str, ctx: minijinja::Value) -> Result<Html<String>, TemplateError> {
+        self.with_env(|env| env.get_template(name)?.render(ctx))
+            .map(Html)
+            .map_err(|e| TemplateError(format!("cannot render {name}: {e:#}")))
+    }
+}
+
+/// Rendering failed, logged and answered with a 500.
+#[derive(Debug)]
+pub struct TemplateError(String);
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl IntoResponse for TemplateError {
+    fn into_response(self) -> Response {
+        log::error!("{self}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "cannot render page").into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use minijinja::context;
+
+    use super::*;
+
+    /// Directory with one file per entry of `TEMPLATES`, all with `source`.
+    fn template_dir(name: &str, source: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "tokio-web-demo-templates-{name}-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        for template in TEMPLATES {
+            std::fs::write(dir.join(template), source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn every_template_is_checked_at_startup() {
+        let mut on_disk: Vec<String> = std::fs::read_dir("templates")
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        on_disk.sort();
+        assert_eq!(on_disk, TEMPLATES);
+        for mode in [TemplateMode::Path, TemplateMode::Reload] {
+            assert!(Templates::new(mode, "templates".into()).is_ok());
+        }
+        // the embedded files don't depend on the directory
+        #[cfg(feature = "embed")]
+        assert!(Templates::new(TemplateMode::Embedded, "no-such-dir".into()).is_ok());
+    }
+
+    #[test]
+    fn templates_render_from_the_template_dir() {
+        let dir = template_dir("render", "hello {{ name }}");
+        let templates = Templates::new(TemplateMode::Path, dir.clone()).unwrap();
+        let Html(page) = templates
+            .render("field.html", context! { name => "field" })
+            .unwrap();
+        assert_eq!(page, "hello field");
+        let e = templates.render("missing.html", context! {}).unwrap_err();
+        assert!(
+            e.to_string().starts_with("cannot render missing.html"),
+            "{e}"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn missing_or_broken_templates_fail_at_startup() {
+        let error = Templates::new(TemplateMode::Path, "no-such-dir".into())
+            .err()
+            .unwrap();
+        assert!(
+            error.starts_with("cannot load template base.html"),
+            "{error}"
+        );
+        let dir = template_dir("broken", "{% if %}");
+        let error = Templates::new(TemplateMode::Path, dir.clone())
+            .err()
+            .unwrap();
+        assert!(
+            error.starts_with("cannot load template base.html"),
+            "{error}"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn modes_parse_from_config_values() {
+        assert_eq!("reload".parse(), Ok(TemplateMode::Reload));
+        assert_eq!("embedded".parse(), Ok(TemplateMode::Embedded));
+        assert!("disk".parse::<TemplateMode>().is_err());
+    }
+}
@@ -8,6 +8,13 @@ document.addEventListener("DOMContentLoaded", () => {
     paintTasksChart(statsHistory);
     paintCpuChart(statsHistory);
     paintMemChart(statsHistory);
+    paintWorkersChart(statsHistory);
+    paintQueuesChart(statsHistory);
+    paintSchedulerChart(statsHistory);
+    // the chart is left out of the page if the feature is disabled
+    if (document.getElementById('soccer_chart')) {
+        paintSoccerChart(statsHistory);
+    }
     // Start Websocket
     let ws_protocol = "wss://";
     if (window.location.protocol === 'http:') {
@@ -15,14 +22,25 @@ document.addEventListener("DOMContentLoaded", () => {
     }
     const statusSocket = new WebSocket(ws_protocol + window.location.host + '/stats/ws');
     statusSocket.onmessage = onStatusMessage;
-    loadSoccerField();
+    // the field is left out of the page if the feature is disabled
+    if (document.getElementById('soccerField')) {
+        loadSoccerField();
+    }
+});
+
+// validation errors (422) are rendered into the form instead of being dropped
+document.addEventListener("htmx:beforeSwap", (event) => {
+    if (event.detail.xhr.status === 422) {
+        event.detail.shouldSwap = true;
+        event.detail.isError = false;
+    }
 });
 
 function onStatusMessage(event) {
     var message = JSON.parse(event.data);
     let time = new Date(message.time);
 
-    document.getElementById('redis_keys_stats').innerHTML = message["keys"];
+    document.getElementById('redis_keys_stats').innerHTML = message["keys"] ?? '-';
     document.getElementById('last_update_stats').innerHTML = time.toLocaleString();
     //console.log(event);
     let messageCountMax = document.getElementById('messageCountMax').getAttribute("value");
@@ -31,6 +49,80 @@ function onStatusMessage(event) {
     updateTasksChart(message, time, messageCountMax);
     updateCpuChart(message, time, messageCountMax);
     updateMemChart(message, time, messageCountMax);
+    updateWorkersChart(message, time, messageCountMax);
+    updateQueuesChart(message, time, messageCountMax);
+    updateSchedulerChart(message, time, messageCountMax);
+    if (charts['soccer_chart']) {
+        updateSoccerChart(message, time, messageCountMax);
+    }
+}
+
+// sum of a per worker field over all workers
+function workersSum(message, field) {
+    return (message.workers || []).reduce((sum, worker) => sum + worker[field], 0);
+}
+
+function updateWorkersChart(message, time, messageCountMax) {
+    const chart = charts['workers_chart'];
+    const workers = message.workers || [];
+    if (chart.data.labels.length > messageCountMax) {
+        chart.data.labels.shift();
+        chart.data.datasets.forEach(dataset => dataset.data.shift());
+    }
+    chart.data.labels.push(time);
+    workers.forEach((worker, i) => {
+        if (chart.data.datasets[i] === undefined) {
+            chart.data.datasets.push(workerDataset(i, chart.data.labels.slice(0, -1).map(() => null)));
+        }
+        chart.data.datasets[i].data.push(worker.busy);
+    });
+    chart.update();
+}
+
+function updateQueuesChart(message, time, messageCountMax) {
+    // update charts
+    if (charts['queues_chart'].data.labels.length > messageCountMax) {
+        charts['queues_chart'].data.labels.shift();
+        charts['queues_chart'].data.datasets[0].data.shift();
+        charts['queues_chart'].data.datasets[1].data.shift();
+        charts['queues_chart'].data.datasets[2].data.shift();
+    }
+    charts['queues_chart'].data.labels.push(time);
+    charts['queues_chart'].data.datasets[0].data.push(message.global_queue);
+    charts['queues_chart'].data.datasets[1].data.push(workersSum(message, 'queue'));
+    charts['queues_chart'].data.datasets[2].data.push(message.blocking_queue);
+    charts['queues_chart'].update();
+}
+
+function updateSchedulerChart(message, time, messageCountMax) {
+    // update charts
+    if (charts['scheduler_chart'].data.labels.length > messageCountMax) {
+        charts['scheduler_chart'].data.labels.shift();
+        charts['scheduler_chart'].data.datasets[0].data.shift();
+        charts['scheduler_chart'].data.datasets[1].data.shift();
+        charts['scheduler_chart'].data.datasets[2].data.shift();
+        charts['scheduler_chart'].data.datasets[3].data.shift();
+    }
+    charts['scheduler_chart'].data.labels.push(time);
+    charts['scheduler_chart'].data.datasets[0].data.push(workersSum(message, 'steals'));
+    charts['scheduler_chart'].data.datasets[1].data.push(message.forced_yields);
+    charts['scheduler_chart'].data.datasets[2].data.push(workersSum(message, 'polls'));
+    charts['scheduler_chart'].data.datasets[3].data.push(workersSum(message, 'parks'));
+    charts['scheduler_chart'].update();
+}
+
+// fields of the soccer telemetry, in the order of the chart's datasets
+const SOCCER_FIELDS = ['tick_max_ms', 'late_max_ms', 'jitter_ms', 'broadcast_max_ms', 'lagged'];
+
+function updateSoccerChart(message, time, messageCountMax) {
+    const chart = charts['soccer_chart'];
+    if (chart.data.labels.length > messageCountMax) {
+        chart.data.labels.shift();
+        chart.data.datasets.forEach(dataset => dataset.data.shift());
+    }
+    chart.data.labels.push(time);
+    SOCCER_FIELDS.forEach((field, i) => chart.data.datasets[i].data.push(message.soccer?.[field] ?? null));
+    chart.update();
 }
 
 function updateMemChart(message, time, messageCountMax) {
@@ -226,6 +318,250 @@ function paintMemChart(statsHistory) {
     charts['mem_chart'] = mem_chart;
 }
 
+const workerColors = ['#198754', '#0d6efd', '#dc3545', '#ffc107', '#6f42c1', '#fd7e14', '#20c997', '#d63384'];
+
+function workerDataset(worker, data) {
+    const color = workerColors[worker % workerColors.length];
+    return {
+        label: `Worker ${worker}`,
+        data: data,
+        backgroundColor: color,
+        borderColor: color,
+        borderWidth: 1
+    };
+}
+
+function paintWorkersChart(statsHistory) {
+    const ctx = document.getElementById('workers_chart').getContext('2d');
+    let workers = Math.max(0, ...statsHistory.map(val => (val.workers || []).length));
+    let datasets = [];
+    for (let i = 0; i < workers; i++) {
+        let data = statsHistory.map(val => val.workers && val.workers[i] ? val.workers[i].busy : null);
+        datasets.push(workerDataset(i, data));
+    }
+    let data_timestamps = statsHistory.map(val => new Date(val.time));
+    const workers_chart = new Chart(ctx, {
+        type: 'line',
+        data: {
+            labels: data_timestamps,
+            datasets: datasets
+        },
+        options: {
+            elements: { point: { radius: 0 } },
+            scales: {
+                y: {
+                    beginAtZero: true,
+                    display: true,
+                    title: {
+                        display: true,
+                        text: '% busy'
+                    },
+                    min: 0,
+                    max: 100
+                },
+                x: {
+                    type: 'timeseries',
+                    display: false
+                }
+            }
+        }
+    });
+    charts['workers_chart'] = workers_chart;
+}
+
+function paintQueuesChart(statsHistory) {
+    const ctx = document.getElementById('queues_chart').getContext('2d');
+    let data_global = statsHistory.map(val => val.global_queue);
+    let data_local = statsHistory.map(val => workersSum(val, 'queue'));
+    let data_blocking = statsHistory.map(val => val.blocking_queue);
+    let data_timestamps = statsHistory.map(val => new Date(val.time));
+    const queues_chart = new Chart(ctx, {
+        type: 'line',
+        data: {
+            labels: data_timestamps,
+            datasets: [{
+                label: "Global Queue",
+                data: data_global,
+                backgroundColor: 'rgba(75, 192, 192, 0.2)',
+                borderColor: 'rgba(75, 192, 192, 1)',
+                borderWidth: 1
+            }, {
+                label: "Local Queues",
+                data: data_local,
+                backgroundColor: '#0d6efd',
+                borderColor: '#0d6efd',
+                borderWidth: 1
+            }, {
+                label: "Blocking Queue",
+                data: data_blocking,
+                backgroundColor: '#dc3545',
+                borderColor: '#dc3545',
+                borderWidth: 1
+            }]
+        },
+        options: {
+            elements: { point: { radius: 0 } },
+            scales: {
+                y: {
+                    beginAtZero: true,
+                    display: true,
+                    title: {
+                        display: true,
+                        text: '#'
+                    },
+                },
+                x: {
+                    type: 'timeseries',
+                    display: false
+                }
+            }
+        }
+    });
+    charts['queues_chart'] = queues_chart;
+}
+
+function paintSchedulerChart(statsHistory) {
+    const ctx = document.getElementById('scheduler_chart').getContext('2d');
+    let data_steals = statsHistory.map(val => workersSum(val, 'steals'));
+    let data_yields = statsHistory.map(val => val.forced_yields);
+    let data_polls = statsHistory.map(val => workersSum(val, 'polls'));
+    let data_parks = statsHistory.map(val => workersSum(val, 'parks'));
+    let data_timestamps = statsHistory.map(val => new Date(val.time));
+    const scheduler_chart = new Chart(ctx, {
+        type: 'line',
+        data: {
+            labels: data_timestamps,
+            datasets: [{
+                label: "Steals",
+                data: data_steals,
+                backgroundColor: 'rgba(75, 192, 192, 0.2)',
+                borderColor: 'rgba(75, 192, 192, 1)',
+                borderWidth: 1
+            }, {
+                label: "Forced Yields",
+                data: data_yields,
+                backgroundColor: '#0d6efd',
+                borderColor: '#0d6efd',
+                borderWidth: 1
+            }, {
+                label: "Polls",
+                data: data_polls,
+                backgroundColor: '#ffc107',
+                borderColor: '#ffc107',
+                borderWidth: 1,
+                yAxisID: 'y1'
+            }, {
+                label: "Parks",
+                data: data_parks,
+                backgroundColor: '#6c757d',
+                borderColor: '#6c757d',
+                borderWidth: 1,
+                yAxisID: 'y1'
+            }]
+        },
+        options: {
+            elements: { point: { radius: 0 } },
+            scales: {
+                y: {
+                    beginAtZero: true,
+                    display: true,
+                    title: {
+                        display: true,
+                        text: '# per interval'
+                    },
+                },
+                y1: {
+                    beginAtZero: true,
+                    display: true,
+                    position: 'right',
+                    grid: { drawOnChartArea: false },
+                    title: {
+                        display: true,
+                        text: 'polls / parks'
+                    },
+                },
+                x: {
+                    type: 'timeseries',
+                    display: false
+                }
+            }
+        }
+    });
+    charts['scheduler_chart'] = scheduler_chart;
+}
+
+function paintSoccerChart(statsHistory) {
+    const ctx = document.getElementById('soccer_chart').getContext('2d');
+    let data = (field) => statsHistory.map(val => val.soccer?.[field] ?? null);
+    let data_timestamps = statsHistory.map(val => new Date(val.time));
+    const soccer_chart = new Chart(ctx, {
+        type: 'line',
+        data: {
+            labels: data_timestamps,
+            datasets: [{
+                label: "Tick",
+                data: data('tick_max_ms'),
+                backgroundColor: 'rgba(75, 192, 192, 0.2)',
+                borderColor: 'rgba(75, 192, 192, 1)',
+                borderWidth: 1
+            }, {
+                label: "Lateness",
+                data: data('late_max_ms'),
+                backgroundColor: '#0d6efd',
+                borderColor: '#0d6efd',
+                borderWidth: 1
+            }, {
+                label: "Jitter",
+                data: data('jitter_ms'),
+                backgroundColor: '#dc3545',
+                borderColor: '#dc3545',
+                borderWidth: 1
+            }, {
+                label: "Broadcast",
+                data: data('broadcast_max_ms'),
+                backgroundColor: '#ffc107',
+                borderColor: '#ffc107',
+                borderWidth: 1
+            }, {
+                label: "Lagged",
+                data: data('lagged'),
+                backgroundColor: '#6c757d',
+                borderColor: '#6c757d',
+                borderWidth: 1,
+                yAxisID: 'y1'
+            }]
+        },
+        options: {
+            elements: { point: { radius: 0 } },
+            scales: {
+                y: {
+                    beginAtZero: true,
+                    display: true,
+                    title: {
+                        display: true,
+                        text: 'ms'
+                    },
+                },
+                y1: {
+                    beginAtZero: true,
+                    display: true,
+                    position: 'right',
+                    grid: { drawOnChartArea: false },
+                    title: {
+                        display: true,
+                        text: 'lagged updates'
+                    },
+                },
+                x: {
+                    type: 'timeseries',
+                    display: false
+                }
+            }
+        }
+    });
+    charts['soccer_chart'] = soccer_chart;
+}
+
 function loadSoccerField() {
     let field = document.getElementById('soccerField');
     let fieldRect = field.getBoundingClientRect();
@@ -237,9 +573,13 @@ function loadSoccerField() {
     if (window.location.protocol === 'http:') {
         ws_protocol = "ws://";
     }
-    const socket = new WebSocket(ws_protocol + window.location.host + '/soccer_field/ws');
+    const socket = new WebSocket(ws_protocol + window.location.host + '/soccer_field/ws?rate=20');
     socket.onmessage = function (event) {
         let doc = JSON.parse(event.data);
+        if (doc.type !== 'positions') {
+            return;
+        }
+        document.getElementById('soccerScore').textContent = `${doc.score.home} : ${doc.score.away}`;
         ball.setAttribute('cy', doc.ball[0]);
         ball.setAttribute('cx', doc.ball[1]);
         for (let [key, value] of Object.entries(doc.players)) {
@@ -252,8 +592,9 @@ function loadSoccerField() {
                 field.appendChild(newElement);
                 player = newElement;
             }
-            player.setAttribute('cy', value[0]);
-            player.setAttribute('cx', value[1]);
+            player.setAttribute('cy', value.position[0]);
+            player.setAttribute('cx', value.position[1]);
+            player.style.fill = doc.colors[value.team];
         }
 
         // cleanup all circles
@@ -0,0 +1,316 @@
+// Canvas renderer of /soccer_field/ws and of replays. All coordinates on the
+// wire are [y, x] in field units, the field is 400 wide and 800 high.
+const FIELD_WIDTH = 400;
+const FIELD_HEIGHT = 800;
+const PLAYER_RADIUS = 8;
+const BALL_RADIUS = 8;
+
+let field = {
+    room: null,
+    replay: null,
+    id: null,
+    team: null,
+    // field state, built from the snapshot and delta frames
+    positions: null,
+    updates: 0,
+    bytes: 0,
+};
+
+document.addEventListener("DOMContentLoaded", () => {
+    const canvas = document.getElementById('fieldCanvas');
+    let ws_protocol = "wss://";
+    if (window.location.protocol === 'http:') {
+        ws_protocol = "ws://";
+    }
+    // room and display name are taken from ?room= and ?name= of the page,
+    // ?replay= and ?speed= play a recording instead of joining, ?spectate=true
+    // watches without a player, optionally with ?fidelity=low
+    let params = new URLSearchParams(window.location.search);
+    let name = params.get('name');
+    let query = '?protocol=binary';
+    if (name) {
+        query += '&name=' + encodeURIComponent(name);
+        document.getElementById('fieldName').value = name;
+    }
+    field.room = params.get('room');
+    field.replay = params.get('replay');
+    let runtime = params.get('runtime');
+    if (runtime) {
+        query += '&runtime=' + encodeURIComponent(runtime);
+        document.getElementById('fieldRuntime').value = runtime;
+    }
+    if (params.get('spectate') === 'true') {
+        query += '&spectate=true&fidelity=' + encodeURIComponent(params.get('fidelity') ?? 'full');
+    }
+    let path = '/soccer_field/ws';
+    if (field.replay) {
+        path = '/soccer_field/replays/' + encodeURIComponent(field.replay) + '/ws';
+        query = '?speed=' + encodeURIComponent(params.get('speed') ?? 1);
+    } else if (field.room) {
+        path += '/' + encodeURIComponent(field.room);
+        document.getElementById('fieldRoom').value = field.room;
+    }
+    const socket = new WebSocket(ws_protocol + window.location.host + path + query);
+    socket.binaryType = 'arraybuffer';
+    socket.onopen = () => setText('fieldStatus', 'connected');
+    socket.onclose = (event) => setText('fieldStatus', `closed (${event.code}${event.reason ? ', ' + event.reason : ''})`);
+    socket.onmessage = onFieldMessage;
+
+    if (!field.replay && params.get('spectate') !== 'true') {
+        trackPointer(canvas, socket);
+    }
+    document.getElementById('fieldBots').addEventListener('submit', setBots);
+    // the server sends faster than the screen refreshes, only draw the latest state
+    const draw = () => {
+        drawField(canvas, field.positions);
+        requestAnimationFrame(draw);
+    };
+    requestAnimationFrame(draw);
+    loadRooms();
+    setInterval(loadRooms, 2000);
+    loadReplays();
+    setInterval(() => {
+        setText('fieldUpdateRate', field.updates);
+        setText('fieldByteRate', field.bytes);
+        field.updates = 0;
+        field.bytes = 0;
+    }, 1000);
+});
+
+function onFieldMessage(event) {
+    if (event.data instanceof ArrayBuffer) {
+        field.bytes += event.data.byteLength;
+        field.positions = decodeFrame(event.data, field.positions);
+        field.updates++;
+        let positions = field.positions;
+        setText('fieldPlayers', Object.keys(positions.players).length);
+        setText('fieldSpectators', positions.spectators);
+        setText('fieldScore', `${positions.score.home} : ${positions.score.away}`);
+        setText('fieldClock', formatClock(positions.clock));
+        return;
+    }
+    let message = JSON.parse(event.data);
+    if (message.type === 'welcome') {
+        field.id = message.id;
+        field.team = message.team;
+        field.colors = message.colors;
+        setText('fieldPlayerId', `#${message.id} (${message.team})`);
+        setText('fieldTickRate', message.tick_rate);
+        setText('fieldRoomRuntime', message.runtime);
+    } else if (message.type === 'spectate') {
+        field.colors = message.colors;
+        setText('fieldPlayerId', `spectating (${message.fidelity} fidelity)`);
+        setText('fieldTickRate', message.tick_rate);
+        setText('fieldRoomRuntime', message.runtime);
+    } else if (message.type === 'error') {
+        console.warn(`soccer field: ${message.code}: ${message.message}`);
+    } else if (message.type === 'replay') {
+        field.colors = message.colors;
+        setText('fieldPlayerId', `replay ${message.name} at ${message.speed}x`);
+        setText('fieldTickRate', message.tick_rate);
+    }
+}
+
+const FRAME_SNAPSHOT = 1;
+const PHASE_CODES = ['first_half', 'half_time', 'second_half', 'full_time'];
+const TEAM_CODES = ['home', 'away'];
+const utf8 = new TextDecoder();
+
+// applies a binary frame to the state, see src/soccer_field/protocol.rs for the layout
+function decodeFrame(buffer, state) {
+    const view = new DataView(buffer);
+    let offset = 0;
+    const u8 = () => view.getUint8(offset++);
+    const u16 = () => { offset += 2; return view.getUint16(offset - 2, true); };
+    const i16 = () => { offset += 2; return view.getInt16(offset - 2, true); };
+    const u32 = () => { offset += 4; return view.getUint32(offset - 4, true); };
+    const score = () => ({ home: u16(), away: u16() });
+    const clock = () => ({ phase: PHASE_CODES[u8()], elapsed_secs: u16(), duration_secs: u16() });
+    const position = () => [i16(), i16()];
+    const player = () => {
+        let id = u16();
+        let team = TEAM_CODES[u8()];
+        let pos = position();
+        let length = u8();
+        let name = length > 0 ? utf8.decode(new Uint8Array(buffer, offset, length)) : null;
+        offset += length;
+        state.players[id] = { position: pos, team: team, name: name };
+    };
+
+    const kind = u8();
+    const tick = u32();
+    if (kind === FRAME_SNAPSHOT) {
+        state = { tick: tick, colors: field.colors, score: score(), clock: clock(), spectators: u16(), ball: position(), players: {} };
+        for (let count = u16(); count > 0; count--) {
+            player();
+        }
+        return state;
+    }
+    state.tick = tick;
+    const flags = u8();
+    if (flags & 1) state.score = score();
+    if (flags & 2) state.clock = clock();
+    if (flags & 8) state.spectators = u16();
+    if (flags & 4) state.ball = position();
+    for (let count = u16(); count > 0; count--) {
+        delete state.players[u16()];
+    }
+    for (let count = u16(); count > 0; count--) {
+        player();
+    }
+    for (let count = u16(); count > 0; count--) {
+        let id = u16();
+        state.players[id].position = position();
+    }
+    return state;
+}
+
+function setBots(event) {
+    event.preventDefault();
+    let form = new FormData(event.target);
+    fetch('/soccer_field/bots', {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({
+            room: field.room ?? undefined,
+            count: Number(form.get('count')),
+            difficulty: form.get('difficulty'),
+        }),
+    })
+        .then(response => response.json())
+        .then(body => setText('fieldBotsStatus', body.error
+            ? body.error.message
+            : `${body.count} ${body.difficulty} bots`));
+}
+
+function loadRooms() {
+    fetch('/soccer_field/rooms')
+        .then(response => response.json())
+        .then(rooms => {
+            let body = document.getElementById('fieldRooms');
+            body.replaceChildren(...rooms.map(room => {
+                let row = document.createElement('tr');
+                let link = document.createElement('a');
+                link.href = '?room=' + encodeURIComponent(room.name);
+                link.textContent = room.name;
+                row.insertCell().appendChild(link);
+                row.insertCell().textContent = room.players;
+                row.insertCell().textContent = room.clients;
+                row.insertCell().textContent = room.spectators;
+                row.insertCell().textContent = room.runtime;
+                return row;
+            }));
+        });
+}
+
+function loadReplays() {
+    fetch('/soccer_field/replays')
+        .then(response => response.json())
+        .then(replays => {
+            let body = document.getElementById('fieldReplays');
+            body.replaceChildren(...replays.map(replay => {
+                let row = document.createElement('tr');
+                let cell = row.insertCell();
+                for (let speed of [1, 4]) {
+                    let link = document.createElement('a');
+                    link.href = `?replay=${encodeURIComponent(replay.name)}&speed=${speed}`;
+                    link.textContent = speed === 1 ? replay.name : `${speed}x`;
+                    cell.append(link, ' ');
+                }
+                row.insertCell().textContent = replay.bytes;
+                return row;
+            }));
+        });
+}
+
+const PHASES = {
+    first_half: '1st half',
+    half_time: 'half time',
+    second_half: '2nd half',
+    full_time: 'full time',
+};
+
+// halves count up, breaks count down to the next kickoff
+function formatClock(clock) {
+    let running = clock.phase === 'first_half' || clock.phase === 'second_half';
+    let secs = running ? clock.elapsed_secs : clock.duration_secs - clock.elapsed_secs;
+    let time = `${Math.floor(secs / 60)}:${String(secs % 60).padStart(2, '0')}`;
+    return `${PHASES[clock.phase]} ${time}`;
+}
+
+function setText(id, text) {
+    document.getElementById(id).textContent = text;
+}
+
+// sends the pointer position as [y, x] whenever it changed, at most every 50ms
+function trackPointer(canvas, socket) {
+    let target = null;
+    let sent = null;
+    canvas.addEventListener('pointermove', (event) => {
+        // the canvas may be scaled by CSS, map back to field units
+        let rect = canvas.getBoundingClientRect();
+        let x = (event.clientX - rect.left) * FIELD_WIDTH / rect.width;
+        let y = (event.clientY - rect.top) * FIELD_HEIGHT / rect.height;
+        target = [Math.round(y), Math.round(x)];
+    });
+    setInterval(() => {
+        if (target == null || socket.readyState !== WebSocket.OPEN) {
+            return;
+        }
+        if (sent == null || target[0] != sent[0] || target[1] != sent[1]) {
+            socket.send(JSON.stringify(target));
+            sent = target;
+        }
+    }, 50);
+}
+
+function drawField(canvas, positions) {
+    const ctx = canvas.getContext('2d');
+    ctx.fillStyle = '#4CAF50';
+    ctx.fillRect(0, 0, FIELD_WIDTH, FIELD_HEIGHT);
+
+    // lines
+    ctx.strokeStyle = 'white';
+    ctx.lineWidth = 2;
+    ctx.strokeRect(0, 0, FIELD_WIDTH, FIELD_HEIGHT);
+    ctx.beginPath();
+    ctx.moveTo(0, FIELD_HEIGHT / 2);
+    ctx.lineTo(FIELD_WIDTH, FIELD_HEIGHT / 2);
+    ctx.stroke();
+    ctx.beginPath();
+    ctx.arc(FIELD_WIDTH / 2, FIELD_HEIGHT / 2, 50, 0, 2 * Math.PI);
+    ctx.stroke();
+    // penalty areas and goals
+    ctx.strokeRect(150, 0, 100, 60);
+    ctx.strokeRect(150, FIELD_HEIGHT - 60, 100, 60);
+    ctx.strokeRect(175, 0, 50, 10);
+    ctx.strokeRect(175, FIELD_HEIGHT - 10, 50, 10);
+
+    if (positions == null) {
+        return;
+    }
+    for (let [id, player] of Object.entries(positions.players)) {
+        let [y, x] = player.position;
+        let own = Number(id) === field.id;
+        ctx.beginPath();
+        ctx.arc(x, y, own ? PLAYER_RADIUS + 2 : PLAYER_RADIUS, 0, 2 * Math.PI);
+        ctx.fillStyle = positions.colors[player.team];
+        ctx.fill();
+        // the own player keeps the team color and gets a yellow ring
+        ctx.lineWidth = own ? 3 : 2;
+        ctx.strokeStyle = own ? '#FFD600' : 'white';
+        ctx.stroke();
+        ctx.fillStyle = 'white';
+        ctx.font = '10px sans-serif';
+        ctx.textAlign = 'center';
+        ctx.fillText(player.name ?? id, x, y - PLAYER_RADIUS - 4);
+    }
+    let [ballY, ballX] = positions.ball;
+    ctx.beginPath();
+    ctx.arc(ballX, ballY, BALL_RADIUS, 0, 2 * Math.PI);
+    ctx.fillStyle = 'white';
+    ctx.fill();
+    ctx.strokeStyle = 'black';
+    ctx.lineWidth = 1;
+    ctx.stroke();
+}